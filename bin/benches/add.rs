@@ -20,24 +20,16 @@ fn setup(c: &mut Criterion) {
         .build()
         .unwrap();
 
-    // Set up the server
-    tokio.spawn(async move {
-        mees_bin::run("localhost:6454").await;
-    });
-
-    // Set up the responder
-    tokio.spawn(async move {
+    // Set up the server and the responder
+    let (broker, client) = tokio.block_on(async {
+        let broker = mees_bin::Embedded::start();
         let mut responder = mees::Responder::new();
         responder.register(Add::handler(|add| async move { add.0 + add.1 }));
-        responder.run("localhost:6454").await.unwrap();
+        broker.responder(responder).await;
+        let client = broker.client().await.unwrap();
+        (broker, client)
     });
 
-    {
-        let _guard = tokio.enter();
-        tokio.block_on(tokio::time::sleep(std::time::Duration::from_millis(500)));
-    }
-
-    let client = tokio.block_on(mees::Client::new("localhost:6454")).unwrap();
     c.bench_with_input(
         BenchmarkId::new("send_add", &client),
         &client,
@@ -45,6 +37,7 @@ fn setup(c: &mut Criterion) {
             b.to_async(&tokio).iter(|| do_something(client));
         },
     );
+    drop(broker);
 }
 
 criterion_group!(benches, setup);
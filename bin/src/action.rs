@@ -1,8 +1,11 @@
 use mees::internals::Message;
 
-use crate::id::ConnectionID;
+use crate::{id::ConnectionID, log::Replay};
 
 pub enum Action {
     Ok,
     Send(ConnectionID, Message),
+    Broadcast(Vec<ConnectionID>, Message),
+    /// Send the events of a log to a connection until the replay is stopped
+    Replay(ConnectionID, Replay),
 }
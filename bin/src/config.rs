@@ -0,0 +1,104 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
+use mees::{
+    transport::{Transport, DEFAULT_MAX_FRAME_SIZE},
+    Address, Backoff, Heartbeat,
+};
+
+use crate::{LogConfig, Strategy};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Pings used to detect connections that stopped responding
+    pub heartbeat: Heartbeat,
+    /// When set, every connection must send this secret in its first frame
+    pub secret: Option<String>,
+    /// The largest frame accepted from or sent to a connection, in bytes,
+    /// a connection sending a larger frame is closed
+    pub max_frame_size: usize,
+    /// How requests are spread over the handlers of a path
+    pub strategy: Strategy,
+    /// Strategies for specific requests by name, such as `"Add"` for every version of
+    /// `Add::path()`, instead of `strategy`
+    pub path_strategies: HashMap<String, Strategy>,
+    /// Other brokers to link to, requests without a handler here are forwarded to them,
+    /// every broker of a federation lists all of the others
+    ///
+    /// A peer sees every path registered here and may answer any request forwarded to it,
+    /// so brokers only accept peers that authenticated with `secret`, and none when it
+    /// is not set
+    pub peers: Vec<Peer>,
+    /// The delay between attempts to link to a peer
+    pub peer_backoff: Backoff,
+    /// How many times a request marked `#[mees(idempotent)]` is sent to another handler
+    /// when its handler is lost, before its caller gets `RequestError::HandlerLost`
+    pub retry_budget: u32,
+    /// When set, published events are kept on disk so subscribers can replay them
+    pub event_log: Option<LogConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            heartbeat: Heartbeat::default(),
+            secret: None,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            strategy: Strategy::default(),
+            path_strategies: HashMap::new(),
+            peers: Vec::new(),
+            peer_backoff: Backoff::default(),
+            retry_budget: 3,
+            event_log: None,
+        }
+    }
+}
+
+/// Another broker to link to, and how to reach it
+#[derive(Clone)]
+pub struct Peer {
+    /// Names the peer in the logs
+    pub name: String,
+    /// Such as `mees::tls::TlsTransport` to link over TLS
+    pub transport: Arc<dyn Transport>,
+    /// The secret the peer expects, the broker's own `secret` when not set
+    pub secret: Option<String>,
+}
+
+impl Peer {
+    pub fn new<T>(name: impl Into<String>, transport: T) -> Self
+    where
+        T: Transport + 'static,
+    {
+        Self {
+            name: name.into(),
+            transport: Arc::new(transport),
+            secret: None,
+        }
+    }
+
+    pub fn with_secret(mut self, secret: impl Into<String>) -> Self {
+        self.secret = Some(secret.into());
+        self
+    }
+}
+
+impl From<Address> for Peer {
+    fn from(address: Address) -> Self {
+        Self::new(address.to_string(), address)
+    }
+}
+
+impl From<&str> for Peer {
+    fn from(address: &str) -> Self {
+        Address::from(address).into()
+    }
+}
+
+impl fmt::Debug for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Peer")
+            .field("name", &self.name)
+            .field("secret", &self.secret.as_ref().map(|_| "..."))
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,86 @@
+use std::sync::Arc;
+
+use mees::{
+    transport::{memory, MemoryTransport},
+    Client, ClientOptions, Responder,
+};
+use tokio::task::JoinHandle;
+
+use crate::{registry::Registry, Config};
+
+/// A broker running inside the current process, reached through in-memory connections
+/// instead of a port, the broker stops when this is dropped
+pub struct Embedded {
+    transport: MemoryTransport,
+    registry: Arc<Registry>,
+    task: JoinHandle<()>,
+}
+
+impl Embedded {
+    pub fn start() -> Self {
+        Self::with_config(Config::default()).expect("the default config opens no event log")
+    }
+
+    /// Fails when the event log can not be opened
+    pub fn with_config(config: Config) -> std::io::Result<Self> {
+        let (transport, listener) = memory();
+        let registry = Arc::new(Registry::new(&config)?);
+        let task = tokio::spawn(crate::serve(listener, config, registry.clone()));
+        Ok(Self {
+            transport,
+            registry,
+            task,
+        })
+    }
+
+    /// A transport connected to this broker, for building clients and responders by hand
+    pub fn transport(&self) -> MemoryTransport {
+        self.transport.clone()
+    }
+
+    pub async fn client(&self) -> Result<Client, Box<dyn std::error::Error>> {
+        self.client_with_options(ClientOptions::default()).await
+    }
+
+    pub async fn client_with_options(
+        &self,
+        options: ClientOptions,
+    ) -> Result<Client, Box<dyn std::error::Error>> {
+        Client::with_transport(self.transport(), options).await
+    }
+
+    /// Wait until at least `count` handlers are registered for `path`, by responders run
+    /// against this broker or on its peers
+    pub async fn wait_registered(&self, path: &str, count: usize) {
+        self.registry.wait_registered(path, count).await;
+    }
+
+    /// Wait until at least `count` clients of this broker are subscribed to live events on
+    /// `path`
+    pub async fn wait_subscribed(&self, path: &str, count: usize) {
+        self.registry.wait_subscribed(path, count).await;
+    }
+
+    /// Run `responder` against this broker, returning once all of its handlers are registered
+    pub async fn responder(&self, responder: Responder) -> JoinHandle<()> {
+        let mut registered = Vec::new();
+        for path in responder.paths() {
+            let count = self.registry.request_subscribers(path).await.len();
+            registered.push((path.to_string(), count));
+        }
+        let transport = self.transport();
+        let task = tokio::spawn(async move {
+            let _ = responder.run_with_transport(&transport).await;
+        });
+        for (path, count) in registered {
+            self.registry.wait_registered(&path, count + 1).await;
+        }
+        task
+    }
+}
+
+impl Drop for Embedded {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
@@ -1,77 +1,362 @@
 use std::{
     collections::{hash_map::Entry, HashMap},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 
-use mees::internals::Message;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
-    net::{TcpListener, ToSocketAddrs},
-    sync::RwLock,
+use mees::{
+    internals::{Control, Message, Payload},
+    transport::{Connection, Listener, SkippedFrame},
+    Address,
+};
+use tokio::sync::{
+    mpsc::{error::TrySendError, Receiver, Sender},
+    RwLock,
 };
 
 mod action;
+mod config;
+mod embedded;
 mod id;
+mod log;
 mod registry;
 
-pub async fn run<A>(addr: A)
+pub use config::{Config, Peer};
+pub use embedded::Embedded;
+pub use log::LogConfig;
+pub use registry::Strategy;
+
+type Connections = Arc<RwLock<HashMap<id::ConnectionID, Sender<Message>>>>;
+
+/// How often pending requests are checked for expired deadlines
+const EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+/// How long a new connection has to finish its handshake, and then to authenticate
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+/// The largest frame accepted before a connection is authenticated
+const AUTH_MAX_FRAME: usize = 1024;
+/// How long to wait before accepting again after the listener failed
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+/// How often the event log is checked for segments past retention
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Run the broker on `addr`, only returning when it can not start
+pub async fn run<A>(addr: A) -> std::io::Result<()>
 where
-    A: ToSocketAddrs,
+    A: Into<Address>,
 {
-    let listener = TcpListener::bind(addr).await.unwrap();
+    run_with_config(addr, Config::default()).await
+}
 
-    let registry = Arc::new(registry::Registry::new());
-    let conn_counter = std::sync::atomic::AtomicU32::new(0);
-    let connections = Arc::new(RwLock::new(HashMap::new()));
+pub async fn run_with_config<A>(addr: A, config: Config) -> std::io::Result<()>
+where
+    A: Into<Address>,
+{
+    let listener = mees::transport::bind(&addr.into()).await?;
+    run_with_listener(listener, config).await
+}
+
+/// Run the broker on connections accepted from any listener, such as
+/// `mees::tls::TlsListener` when the `tls` feature is enabled
+///
+/// Fails when the event log can not be opened
+pub async fn run_with_listener<L>(listener: L, config: Config) -> std::io::Result<()>
+where
+    L: Listener + 'static,
+{
+    let registry = Arc::new(registry::Registry::new(&config)?);
+    serve(listener, config, registry).await;
+    Ok(())
+}
+
+async fn serve<L>(listener: L, config: Config, registry: Arc<registry::Registry>)
+where
+    L: Listener + 'static,
+{
+    let listener = Arc::new(listener);
+    let broker = Arc::new(Broker {
+        config,
+        registry,
+        connections: Arc::new(RwLock::new(HashMap::new())),
+        conn_counter: AtomicU32::new(0),
+    });
+
+    {
+        let broker = broker.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(EXPIRE_INTERVAL);
+            loop {
+                interval.tick().await;
+                for action in broker.registry.expire_pending().await {
+                    dispatch(&broker.connections, action).await;
+                }
+            }
+        });
+    }
+
+    if broker.config.event_log.is_some() {
+        let registry = broker.registry.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+            loop {
+                interval.tick().await;
+                registry.retain_events().await;
+            }
+        });
+    }
+
+    for peer in &broker.config.peers {
+        tokio::spawn(broker.clone().link(peer.clone()));
+    }
 
     loop {
-        let (mut socket, _) = listener.accept().await.unwrap();
-        let (tx, mut rx) = tokio::sync::mpsc::channel::<Message>(32);
-        let conn_id = loop {
-            let conn_id =
-                id::ConnectionID(conn_counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed));
-            if let Entry::Vacant(e) = connections.write().await.entry(conn_id) {
-                e.insert(tx);
-                break conn_id;
+        let socket = match listener.accept().await {
+            Ok(socket) => socket,
+            Err(e) => {
+                println!("Failed to accept a connection: {e}");
+                // Only the connection that was being accepted is lost, but running out of
+                // file descriptors passes once others close, so wait before trying again
+                if !matches!(
+                    e.kind(),
+                    std::io::ErrorKind::ConnectionAborted
+                        | std::io::ErrorKind::ConnectionReset
+                        | std::io::ErrorKind::Interrupted
+                ) {
+                    tokio::time::sleep(ACCEPT_BACKOFF).await;
+                }
+                continue;
             }
         };
-        let registry = registry.clone();
-        let connections = connections.clone();
+        let (tx, rx) = tokio::sync::mpsc::channel::<Message>(32);
+        let conn_id = broker.add_connection(tx.clone()).await;
+        let broker = broker.clone();
+        let listener = listener.clone();
 
         tokio::spawn(async move {
-            // Wait for either a message from the socket or a message from the registry
-            let (read, write) = socket.split();
-            let mut read = BufReader::new(read);
-            let mut write = BufWriter::new(write);
-            let mut buffer = [0; 1024];
+            let config = &broker.config;
+            // A peer that stalls the handshake is dropped like one that never authenticates
+            let handshake = tokio::time::timeout(AUTH_TIMEOUT, listener.handshake(socket))
+                .await
+                .unwrap_or_else(|_| {
+                    Err(std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "the handshake timed out",
+                    ))
+                });
+            let mut connection = match handshake {
+                Ok(socket) => Connection::with_max_frame_size(socket, config.max_frame_size),
+                Err(e) => {
+                    println!("Handshake failed for {conn_id:?}: {e}");
+                    broker.connections.write().await.remove(&conn_id);
+                    return;
+                }
+            };
+            if let Some(secret) = &config.secret {
+                connection.set_max_frame_size(AUTH_MAX_FRAME.min(config.max_frame_size));
+                if !authenticate(&mut connection, secret).await {
+                    println!("Rejected unauthenticated connection: {conn_id:?}");
+                    broker.connections.write().await.remove(&conn_id);
+                    return;
+                }
+                connection.set_max_frame_size(config.max_frame_size);
+            }
+            broker.run_connection(conn_id, connection, tx, rx).await;
+        });
+    }
+}
+
+/// The state shared by the tasks of a broker
+struct Broker {
+    config: Config,
+    registry: Arc<registry::Registry>,
+    connections: Connections,
+    conn_counter: AtomicU32,
+}
+
+impl Broker {
+    async fn add_connection(&self, tx: Sender<Message>) -> id::ConnectionID {
+        loop {
+            let conn_id = id::ConnectionID(self.conn_counter.fetch_add(1, Ordering::Relaxed));
+            if let Entry::Vacant(e) = self.connections.write().await.entry(conn_id) {
+                e.insert(tx);
+                break conn_id;
+            }
+        }
+    }
+
+    /// Pass messages between a connection and the registry until it closes, then forget it
+    async fn run_connection(
+        &self,
+        conn_id: id::ConnectionID,
+        connection: Connection,
+        tx: Sender<Message>,
+        mut rx: Receiver<Message>,
+    ) {
+        let (mut read, mut write) = connection.into_split();
+        let missed = AtomicU32::new(0);
+        // Messages from the socket are handled by the registry
+        let reader = async {
             loop {
-                tokio::select! {
-                    Some(msg) = rx.recv() => {
-                        let msg = msg.to_bytes();
-                        write.write_u32(msg.len() as u32).await.unwrap();
-                        write.write_all(&msg).await.unwrap();
-                        write.flush().await.unwrap();
+                let msg = match read.read().await {
+                    Ok(Some(msg)) => msg,
+                    Ok(None) => break,
+                    Err(e) => {
+                        if let Some(skipped) = SkippedFrame::from_io(&e) {
+                            println!("Skipped frame from {conn_id:?}: {}", skipped.error);
+                            missed.store(0, Ordering::Relaxed);
+                            for action in self.registry.skipped(conn_id, skipped).await {
+                                dispatch(&self.connections, action).await;
+                            }
+                            continue;
+                        }
+                        println!("Closing connection {conn_id:?}: {e}");
+                        break;
                     }
-                    res = read.read_u32() => {
-                        let n = res.unwrap();
-                        let buf = &mut buffer[..n as usize];
-                        if n == 0 {
+                };
+                missed.store(0, Ordering::Relaxed);
+                if matches!(msg.payload, Payload::Control(Control::Disconnect)) {
+                    break;
+                }
+                let action = self.registry.handle_message(conn_id, msg).await;
+                dispatch(&self.connections, action).await;
+            }
+        };
+        // Messages from the registry are written to the socket
+        let writer = async {
+            while let Some(msg) = rx.recv().await {
+                if let Err(e) = write.write(&msg).await {
+                    println!("Closing connection {conn_id:?}: {e}");
+                    break;
+                }
+            }
+        };
+        // A full queue skips the ping rather than waiting on a connection that may be stuck
+        let heartbeat = self.config.heartbeat.monitor(&missed, || async {
+            !matches!(
+                tx.try_send(Message::new(Payload::Control(Control::Ping))),
+                Err(TrySendError::Closed(_))
+            )
+        });
+        tokio::select! {
+            () = reader => {}
+            () = writer => {}
+            () = heartbeat => println!("Evicting unresponsive connection: {conn_id:?}"),
+        }
+        // However the connection ended, nothing may be routed to it anymore
+        self.connections.write().await.remove(&conn_id);
+        for action in self.registry.remove_connection(conn_id).await {
+            dispatch(&self.connections, action).await;
+        }
+    }
+
+    /// Keep a link to a peer broker, announcing the paths handled here so the peer
+    /// forwards the requests it has no handler for
+    async fn link(self: Arc<Self>, peer: Peer) {
+        let mut attempt = 0u32;
+        loop {
+            match self.dial(&peer).await {
+                Ok(mut connection) => {
+                    attempt = 0;
+                    let (tx, rx) = tokio::sync::mpsc::channel::<Message>(32);
+                    let conn_id = self.add_connection(tx.clone()).await;
+                    // Written before anything the registry sends on the link
+                    let mut introduced = Ok(());
+                    for message in self.registry.add_link(conn_id).await {
+                        introduced = connection.write(&message).await;
+                        if introduced.is_err() {
                             break;
                         }
-                        let _ = read.read_exact(buf).await.unwrap();
-                        let msg = Message::from_bytes(buf);
-                        let action = registry.handle_message(conn_id, msg).await;
-                        match action {
-                            action::Action::Ok=>{}
-                            action::Action::Send(connection, message) => {
-                                if let Some(sender) = connections.read().await.get(&connection) {
-                                    sender.send(message).await.unwrap();
-                                }
+                    }
+                    match introduced {
+                        Ok(()) => {
+                            println!("Linked to peer broker {}", peer.name);
+                            self.run_connection(conn_id, connection, tx, rx).await;
+                        }
+                        Err(e) => {
+                            println!("Failed to link to peer broker {}: {e}", peer.name);
+                            self.connections.write().await.remove(&conn_id);
+                            for action in self.registry.remove_connection(conn_id).await {
+                                dispatch(&self.connections, action).await;
                             }
                         }
                     }
                 }
+                Err(e) => {
+                    println!("Failed to link to peer broker {}: {e}", peer.name);
+                    attempt = attempt.saturating_add(1);
+                }
             }
-        });
+            tokio::time::sleep(self.config.peer_backoff.delay(attempt)).await;
+        }
+    }
+
+    async fn dial(&self, peer: &Peer) -> std::io::Result<Connection> {
+        let mut connection = Connection::with_max_frame_size(
+            peer.transport.connect().await?,
+            self.config.max_frame_size,
+        );
+        if let Some(secret) = peer.secret.as_ref().or(self.config.secret.as_ref()) {
+            mees::internals::authenticate(&mut connection, secret).await?;
+        }
+        Ok(connection)
+    }
+}
+
+/// Wait for the first frame and check that it carries the shared secret
+async fn authenticate(connection: &mut Connection, secret: &str) -> bool {
+    let first = tokio::time::timeout(AUTH_TIMEOUT, connection.read()).await;
+    let accepted = matches!(
+        first,
+        Ok(Ok(Some(Message {
+            payload: Payload::Control(Control::AuthPass(ref pass)),
+        }))) if constant_time_eq(pass.as_bytes(), secret.as_bytes())
+    );
+    let reply = if accepted {
+        Control::AuthOk
+    } else {
+        Control::Disconnect
+    };
+    let _ = connection
+        .write(&Message::new(Payload::Control(reply)))
+        .await;
+    accepted
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Queue the messages of an action, the connections are unlocked before waiting on a
+/// full queue so a stuck connection does not hold up the others
+async fn dispatch(connections: &Connections, action: action::Action) {
+    match action {
+        action::Action::Ok => {}
+        action::Action::Send(connection, message) => {
+            // The connection may be closing, it is removed once its task ends
+            let sender = connections.read().await.get(&connection).cloned();
+            if let Some(sender) = sender {
+                let _ = sender.send(message).await;
+            }
+        }
+        action::Action::Broadcast(targets, message) => {
+            let senders = {
+                let connections = connections.read().await;
+                targets
+                    .iter()
+                    .filter_map(|connection| connections.get(connection).cloned())
+                    .collect::<Vec<_>>()
+            };
+            for sender in senders {
+                let _ = sender.send(message.clone()).await;
+            }
+        }
+        action::Action::Replay(connection, replay) => {
+            let sender = connections.read().await.get(&connection).cloned();
+            if let Some(sender) = sender {
+                tokio::spawn(replay.run(sender));
+            }
+        }
     }
 }
@@ -0,0 +1,617 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use mees::{
+    internals::{EventPublish, Message, Payload},
+    Position,
+};
+use tokio::sync::{mpsc::Sender, oneshot, watch};
+
+/// The size of a record header: its offset, timestamp and data length
+const HEADER: u64 = 8 + 8 + 4;
+/// The most records read from the log at once while replaying
+const REPLAY_BATCH: usize = 256;
+
+/// Where and for how long published events are kept
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// The directory holding a directory of segments for each event path
+    pub dir: PathBuf,
+    /// A new segment is started once the current one reaches this size, in bytes
+    pub segment_size: u64,
+    /// The oldest segments of an event path are removed while it is larger than this, in bytes
+    pub max_size: Option<u64>,
+    /// Segments are removed once their newest event is older than this
+    pub max_age: Option<Duration>,
+    /// Wait for each event to reach the disk before it is delivered, so a power loss
+    /// can not lose it, otherwise writing it out is left to the operating system
+    pub sync: bool,
+}
+
+impl LogConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            segment_size: 64 * 1024 * 1024,
+            max_size: None,
+            max_age: None,
+            sync: true,
+        }
+    }
+}
+
+/// The append-only log of every event path, each one split into segment files
+///
+/// Its methods block on the disk, the async ones run them on the blocking thread pool
+#[derive(Debug)]
+pub(crate) struct EventLog {
+    config: LogConfig,
+    /// Topics by the name of their directory
+    topics: Mutex<HashMap<String, Arc<Topic>>>,
+}
+
+impl EventLog {
+    /// Open the log, along with every topic already on disk so retention applies to them
+    pub fn open(config: LogConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.dir)?;
+        let mut topics = HashMap::new();
+        for entry in fs::read_dir(&config.dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let topic = Topic::open(entry.path(), &config)?;
+                topics.insert(name, Arc::new(topic));
+            }
+        }
+        Ok(Self {
+            config,
+            topics: Mutex::new(topics),
+        })
+    }
+
+    /// The topic of an event path, created on first use
+    pub fn topic(&self, path: &str) -> io::Result<Arc<Topic>> {
+        let name = topic_name(path);
+        let mut topics = self.topics.lock().unwrap();
+        if let Some(topic) = topics.get(&name) {
+            return Ok(topic.clone());
+        }
+        let topic = Arc::new(Topic::open(self.config.dir.join(&name), &self.config)?);
+        topics.insert(name, topic.clone());
+        Ok(topic)
+    }
+
+    /// Append an event to the topic of `path`, returning its offset along with the data
+    pub async fn append(
+        self: &Arc<Self>,
+        path: String,
+        data: Vec<u8>,
+    ) -> (io::Result<u64>, Vec<u8>) {
+        let log = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let offset = log.topic(&path).and_then(|topic| topic.append(&data));
+            (offset, data)
+        })
+        .await
+        .expect("appending to the event log panicked")
+    }
+
+    /// The topic of `path` and a cursor at the first event at or after `from`
+    pub async fn cursor(
+        self: &Arc<Self>,
+        path: String,
+        from: Position,
+    ) -> io::Result<(Arc<Topic>, Cursor)> {
+        let log = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let topic = log.topic(&path)?;
+            let cursor = topic.cursor(from)?;
+            Ok((topic, cursor))
+        })
+        .await
+        .expect("opening the event log panicked")
+    }
+
+    /// Remove the segments that are past retention from every topic
+    pub fn retain(&self) {
+        let topics = self
+            .topics
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        for topic in topics {
+            let mut state = topic.state.lock().unwrap();
+            if let Err(e) = topic.retain(&mut state) {
+                println!("Failed to apply retention in {:?}: {e}", topic.dir);
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Segment {
+    /// The offset of the first record, also the name of the file
+    base: u64,
+    size: u64,
+}
+
+#[derive(Debug)]
+struct TopicState {
+    /// Oldest first, records are appended to the last one
+    segments: Vec<Segment>,
+    active: File,
+    /// The offset of the next record
+    next: u64,
+}
+
+/// The log of a single event path
+#[derive(Debug)]
+pub(crate) struct Topic {
+    dir: PathBuf,
+    segment_size: u64,
+    max_size: Option<u64>,
+    max_age: Option<Duration>,
+    sync: bool,
+    state: Mutex<TopicState>,
+    /// Sent the next offset after every append, to wake replays that caught up
+    appended: watch::Sender<u64>,
+}
+
+impl Topic {
+    fn open(dir: PathBuf, config: &LogConfig) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        let mut segments = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "log") {
+                if let Some(base) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse().ok())
+                {
+                    let size = fs::metadata(&path)?.len();
+                    segments.push(Segment { base, size });
+                }
+            }
+        }
+        segments.sort_by_key(|segment| segment.base);
+        if segments.is_empty() {
+            segments.push(Segment { base: 0, size: 0 });
+        }
+        let last = segments.last_mut().expect("there is a segment");
+        let active = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(segment_path(&dir, last.base))?;
+        // A record cut short by a crash is dropped, appends continue after the last whole one
+        let (size, next) = scan(&active, last.base)?;
+        active.set_len(size)?;
+        last.size = size;
+        let topic = Self {
+            dir,
+            segment_size: config.segment_size,
+            max_size: config.max_size,
+            max_age: config.max_age,
+            sync: config.sync,
+            state: Mutex::new(TopicState {
+                segments,
+                active,
+                next,
+            }),
+            appended: watch::channel(next).0,
+        };
+        topic.retain(&mut topic.state.lock().unwrap())?;
+        Ok(topic)
+    }
+
+    /// Append an event, returning its offset
+    pub fn append(&self, data: &[u8]) -> io::Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        let offset = state.next;
+        let len = u32::try_from(data.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "event too large"))?;
+        let mut record = Vec::with_capacity(HEADER as usize + data.len());
+        record.extend_from_slice(&offset.to_be_bytes());
+        record.extend_from_slice(&unix_millis(SystemTime::now()).to_be_bytes());
+        record.extend_from_slice(&len.to_be_bytes());
+        record.extend_from_slice(data);
+        let written = state.active.write_all(&record).and_then(|()| {
+            if self.sync {
+                state.active.sync_data()
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(e) = written {
+            // Drop whatever part of the record was written, so the next one follows a whole one
+            let size = state.segments.last().expect("there is a segment").size;
+            let _ = state.active.set_len(size);
+            return Err(e);
+        }
+        state.next += 1;
+        let segment = state.segments.last_mut().expect("there is a segment");
+        segment.size += record.len() as u64;
+        if segment.size >= self.segment_size {
+            let base = state.next;
+            state.active = OpenOptions::new()
+                .create(true)
+                .read(true)
+                .append(true)
+                .open(segment_path(&self.dir, base))?;
+            if self.sync {
+                // The new file is only durable once its directory entry is
+                File::open(&self.dir)?.sync_all()?;
+            }
+            state.segments.push(Segment { base, size: 0 });
+            self.retain(&mut state)?;
+        }
+        self.appended.send_replace(state.next);
+        Ok(offset)
+    }
+
+    /// Remove the oldest segments while the topic is too large or they are too old,
+    /// the active segment is always kept
+    fn retain(&self, state: &mut TopicState) -> io::Result<()> {
+        let mut total = state.segments.iter().map(|s| s.size).sum::<u64>();
+        while state.segments.len() > 1 {
+            let oldest = &state.segments[0];
+            let path = segment_path(&self.dir, oldest.base);
+            let too_large = self.max_size.is_some_and(|max| total > max);
+            let too_old = match self.max_age {
+                Some(max_age) => fs::metadata(&path)?
+                    .modified()?
+                    .elapsed()
+                    .is_ok_and(|age| age > max_age),
+                None => false,
+            };
+            if !too_large && !too_old {
+                break;
+            }
+            total -= oldest.size;
+            fs::remove_file(path)?;
+            state.segments.remove(0);
+        }
+        Ok(())
+    }
+
+    /// The segments as they are now, so they can be read without holding the lock
+    fn segments(&self) -> Vec<(u64, u64)> {
+        let state = self.state.lock().unwrap();
+        state.segments.iter().map(|s| (s.base, s.size)).collect()
+    }
+
+    /// A cursor at the first event at or after `from`
+    pub fn cursor(&self, from: Position) -> io::Result<Cursor> {
+        let segments = self.segments();
+        let (segment, since) = match from {
+            Position::Offset(offset) => (
+                segments
+                    .iter()
+                    .rev()
+                    .find(|(base, _)| *base <= offset)
+                    .unwrap_or(&segments[0])
+                    .0,
+                None,
+            ),
+            Position::Time(time) => {
+                // A segment last written before `time` holds no event to replay
+                let mut segment = segments.last().expect("there is a segment").0;
+                for (base, _) in &segments {
+                    let modified = match fs::metadata(segment_path(&self.dir, *base)) {
+                        Ok(metadata) => metadata.modified()?,
+                        // Removed by retention since the segments were listed
+                        Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                        Err(e) => return Err(e),
+                    };
+                    if modified >= time {
+                        segment = *base;
+                        break;
+                    }
+                }
+                (segment, Some(unix_millis(time)))
+            }
+        };
+        Ok(Cursor {
+            segment,
+            position: 0,
+            offset: match from {
+                Position::Offset(offset) => offset,
+                Position::Time(_) => 0,
+            },
+            since,
+        })
+    }
+
+    /// Read up to `max` events after the cursor, with their offsets
+    ///
+    /// The segments are read without holding the lock, only the whole records appended
+    /// before the read started are seen
+    pub fn read(&self, cursor: &mut Cursor, max: usize) -> io::Result<Vec<(u64, Vec<u8>)>> {
+        let mut events = Vec::new();
+        loop {
+            match self.read_segments(cursor, max, &mut events) {
+                // The segment was removed by retention while it was read, start over
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                result => return result.map(|()| events),
+            }
+        }
+    }
+
+    fn read_segments(
+        &self,
+        cursor: &mut Cursor,
+        max: usize,
+        events: &mut Vec<(u64, Vec<u8>)>,
+    ) -> io::Result<()> {
+        let segments = self.segments();
+        let mut index = match segments
+            .iter()
+            .position(|(base, _)| *base == cursor.segment)
+        {
+            Some(index) => index,
+            None => {
+                // The segment was removed by retention, continue from the oldest one left
+                cursor.segment = segments[0].0;
+                cursor.position = 0;
+                0
+            }
+        };
+        while events.len() < max {
+            let (base, size) = segments[index];
+            if cursor.position < size {
+                let mut file = File::open(segment_path(&self.dir, base))?;
+                file.seek(SeekFrom::Start(cursor.position))?;
+                let mut reader = BufReader::new(file.take(size - cursor.position));
+                while events.len() < max && cursor.position < size {
+                    let (offset, time, data) = read_record(&mut reader)?;
+                    cursor.position += HEADER + data.len() as u64;
+                    if offset < cursor.offset || cursor.since.is_some_and(|since| time < since) {
+                        continue;
+                    }
+                    // Everything after the first match is replayed, even if the clock went back
+                    cursor.since = None;
+                    cursor.offset = offset + 1;
+                    events.push((offset, data));
+                }
+            } else if index + 1 < segments.len() {
+                index += 1;
+                cursor.segment = segments[index].0;
+                cursor.position = 0;
+            } else {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A position in a topic that a replay reads from
+#[derive(Debug)]
+pub(crate) struct Cursor {
+    segment: u64,
+    /// The byte position in the segment
+    position: u64,
+    /// Records before this offset are skipped
+    offset: u64,
+    /// Records logged before this time, in unix milliseconds, are skipped
+    since: Option<u64>,
+}
+
+/// Sends the events of a topic to a subscriber, from a cursor and then as they are appended
+#[derive(Debug)]
+pub(crate) struct Replay {
+    pub topic: Arc<Topic>,
+    pub cursor: Cursor,
+    pub path: String,
+    /// The replay ends once the sender is dropped
+    pub stop: oneshot::Receiver<()>,
+}
+
+impl Replay {
+    pub async fn run(mut self, sender: Sender<Message>) {
+        loop {
+            let mut appended = self.topic.appended.subscribe();
+            let topic = self.topic.clone();
+            let mut cursor = self.cursor;
+            let (cursor, events) = tokio::task::spawn_blocking(move || {
+                let events = topic.read(&mut cursor, REPLAY_BATCH);
+                (cursor, events)
+            })
+            .await
+            .expect("reading the event log panicked");
+            self.cursor = cursor;
+            let events = match events {
+                Ok(events) => events,
+                Err(e) => {
+                    println!("Failed to replay {:?}: {e}", self.path);
+                    return;
+                }
+            };
+            if events.is_empty() {
+                tokio::select! {
+                    _ = appended.changed() => continue,
+                    _ = &mut self.stop => return,
+                }
+            }
+            for (offset, data) in events {
+                if !matches!(
+                    self.stop.try_recv(),
+                    Err(oneshot::error::TryRecvError::Empty)
+                ) {
+                    return;
+                }
+                let event = EventPublish {
+                    path: self.path.clone(),
+                    data,
+                    offset: Some(offset),
+                };
+                if sender
+                    .send(Message::new(Payload::EventPublish(event)))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn segment_path(dir: &Path, base: u64) -> PathBuf {
+    dir.join(format!("{base:020}.log"))
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+fn read_record(reader: &mut impl Read) -> io::Result<(u64, u64, Vec<u8>)> {
+    let mut header = [0; HEADER as usize];
+    reader.read_exact(&mut header)?;
+    let offset = u64::from_be_bytes(header[0..8].try_into().unwrap());
+    let time = u64::from_be_bytes(header[8..16].try_into().unwrap());
+    let len = u32::from_be_bytes(header[16..20].try_into().unwrap());
+    let mut data = vec![0; len as usize];
+    reader.read_exact(&mut data)?;
+    Ok((offset, time, data))
+}
+
+/// Find the end of the last whole record in a segment, and the offset after it
+fn scan(file: &File, base: u64) -> io::Result<(u64, u64)> {
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(0))?;
+    let mut size = 0;
+    let mut next = base;
+    loop {
+        match read_record(&mut reader) {
+            Ok((offset, _, data)) => {
+                size += HEADER + data.len() as u64;
+                next = offset + 1;
+            }
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok((size, next))
+}
+
+/// The directory name of a topic, every byte of the path that is not alphanumeric or `-`
+/// is written as `_` and two hex digits, so no two paths share a directory
+fn topic_name(path: &str) -> String {
+    let mut name = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' {
+            name.push(char::from(byte));
+        } else {
+            name.push_str(&format!("_{byte:02x}"));
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("mees-log-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn reopen() {
+        let dir = temp_dir("reopen");
+        let config = LogConfig {
+            segment_size: 64,
+            ..LogConfig::new(&dir)
+        };
+        let log = EventLog::open(config.clone()).unwrap();
+        let topic = log.topic("Tick-1").unwrap();
+        for i in 0..10u8 {
+            assert_eq!(topic.append(&[i; 10]).unwrap(), u64::from(i));
+        }
+        drop((topic, log));
+        // A record cut short is dropped when the log is opened again
+        let active = fs::read_dir(dir.join("Tick-1"))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .max()
+            .unwrap();
+        OpenOptions::new()
+            .append(true)
+            .open(active)
+            .unwrap()
+            .write_all(&[0; 5])
+            .unwrap();
+
+        let log = EventLog::open(config).unwrap();
+        let topic = log.topic("Tick-1").unwrap();
+        assert_eq!(topic.append(&[10; 10]).unwrap(), 10);
+        let mut cursor = topic.cursor(Position::Offset(3)).unwrap();
+        let events = topic.read(&mut cursor, 100).unwrap();
+        assert_eq!(
+            events,
+            (3..11u8)
+                .map(|i| (u64::from(i), vec![i; 10]))
+                .collect::<Vec<_>>()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn distinct_topics() {
+        let dir = temp_dir("distinct");
+        let log = EventLog::open(LogConfig::new(&dir)).unwrap();
+        let dotted = log.topic("a.b-1").unwrap();
+        let underscored = log.topic("a_b-1").unwrap();
+        assert!(!Arc::ptr_eq(&dotted, &underscored));
+        assert_eq!(dotted.append(b"dotted").unwrap(), 0);
+        assert_eq!(underscored.append(b"underscored").unwrap(), 0);
+        drop((dotted, underscored, log));
+
+        // Each path finds its own events again once the log is reopened
+        let log = EventLog::open(LogConfig::new(&dir)).unwrap();
+        for (path, event) in [("a.b-1", &b"dotted"[..]), ("a_b-1", &b"underscored"[..])] {
+            let topic = log.topic(path).unwrap();
+            let mut cursor = topic.cursor(Position::Offset(0)).unwrap();
+            assert_eq!(topic.read(&mut cursor, 10).unwrap(), [(0, event.to_vec())]);
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn retention() {
+        let dir = temp_dir("retention");
+        let log = EventLog::open(LogConfig {
+            segment_size: 60,
+            max_size: Some(120),
+            ..LogConfig::new(&dir)
+        })
+        .unwrap();
+        let topic = log.topic("Tick-1").unwrap();
+        for i in 0..20u8 {
+            topic.append(&[i; 10]).unwrap();
+        }
+        // Each segment holds two records, at most two whole segments are kept
+        let mut cursor = topic.cursor(Position::Offset(0)).unwrap();
+        let offsets = topic
+            .read(&mut cursor, 100)
+            .unwrap()
+            .into_iter()
+            .map(|(offset, _)| offset)
+            .collect::<Vec<_>>();
+        assert_eq!(offsets, [16, 17, 18, 19]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,5 +1,121 @@
 #[tokio::main]
 async fn main() {
     let addr = std::env::var("MEES_ADDR").unwrap_or_else(|_| "localhost:6454".to_string());
-    mees_bin::run(addr).await;
+    let config = mees_bin::Config {
+        secret: std::env::var("MEES_SECRET").ok(),
+        max_frame_size: std::env::var("MEES_MAX_FRAME_SIZE")
+            .map(|size| {
+                size.parse()
+                    .expect("MEES_MAX_FRAME_SIZE must be a number of bytes")
+            })
+            .unwrap_or(mees::transport::DEFAULT_MAX_FRAME_SIZE),
+        strategy: std::env::var("MEES_STRATEGY")
+            .map(|strategy| parse_strategy("MEES_STRATEGY", &strategy))
+            .unwrap_or_default(),
+        // Such as `Add=weighted,Get=random`
+        path_strategies: std::env::var("MEES_PATH_STRATEGIES")
+            .map(|strategies| {
+                strategies
+                    .split(',')
+                    .map(|strategy| {
+                        let (name, strategy) = strategy.split_once('=').unwrap_or_else(|| {
+                            exit(&format!(
+                                "MEES_PATH_STRATEGIES must be a list of name=strategy, got {strategy:?}"
+                            ))
+                        });
+                        (
+                            name.to_string(),
+                            parse_strategy("MEES_PATH_STRATEGIES", strategy),
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default(),
+        peers: std::env::var("MEES_PEERS")
+            .map(|peers| peers.split(',').map(peer).collect())
+            .unwrap_or_default(),
+        retry_budget: std::env::var("MEES_RETRY_BUDGET")
+            .map(|budget| budget.parse().expect("MEES_RETRY_BUDGET must be a number"))
+            .unwrap_or(3),
+        event_log: std::env::var("MEES_EVENT_LOG")
+            .ok()
+            .map(|dir| mees_bin::LogConfig {
+                max_size: std::env::var("MEES_EVENT_LOG_MAX_SIZE").ok().map(|size| {
+                    size.parse()
+                        .expect("MEES_EVENT_LOG_MAX_SIZE must be a number of bytes")
+                }),
+                max_age: std::env::var("MEES_EVENT_LOG_MAX_AGE").ok().map(|age| {
+                    std::time::Duration::from_secs(
+                        age.parse()
+                            .expect("MEES_EVENT_LOG_MAX_AGE must be a number of seconds"),
+                    )
+                }),
+                sync: std::env::var("MEES_EVENT_LOG_SYNC").map_or(true, |sync| {
+                    sync.parse()
+                        .expect("MEES_EVENT_LOG_SYNC must be true or false")
+                }),
+                ..mees_bin::LogConfig::new(dir)
+            }),
+        ..mees_bin::Config::default()
+    };
+    let listener = mees::transport::bind(&addr.as_str().into())
+        .await
+        .unwrap_or_else(|e| exit(&format!("Failed to listen on {addr}: {e}")));
+    #[cfg(feature = "tls")]
+    let listener: Box<dyn mees::transport::Listener> = match tls() {
+        Some(tls) => Box::new(mees::tls::TlsListener::new(listener, tls)),
+        None => listener,
+    };
+    if let Err(e) = mees_bin::run_with_listener(listener, config).await {
+        exit(&format!("Failed to start the broker: {e}"));
+    }
+}
+
+/// A peer from `MEES_PEERS`, linked to over TLS when `MEES_PEER_TLS_CA` names the
+/// certificate authority its certificate is checked against
+fn peer(address: &str) -> mees_bin::Peer {
+    #[cfg(feature = "tls")]
+    if let Ok(ca) = std::env::var("MEES_PEER_TLS_CA") {
+        let address = mees::Address::from(address);
+        let mees::Address::Tcp(host) = &address else {
+            exit(&format!("MEES_PEER_TLS_CA needs tcp peers, got {address}"))
+        };
+        let server_name = host
+            .rsplit_once(':')
+            .map_or(host.as_str(), |(name, _)| name);
+        let ca = std::fs::read(&ca).unwrap_or_else(|e| exit(&format!("MEES_PEER_TLS_CA: {e}")));
+        let tls = mees::tls::TlsClientConfig::from_pem(&ca, server_name)
+            .unwrap_or_else(|e| exit(&format!("MEES_PEER_TLS_CA: {e}")));
+        return mees_bin::Peer::new(
+            address.to_string(),
+            mees::tls::TlsTransport::new(address, tls),
+        );
+    }
+    address.into()
+}
+
+fn parse_strategy(var: &str, strategy: &str) -> mees_bin::Strategy {
+    strategy.parse().unwrap_or_else(|e| {
+        exit(&format!(
+            "{var}: {e}, expected round-robin, least-outstanding, random or weighted"
+        ))
+    })
+}
+
+fn exit(message: &str) -> ! {
+    eprintln!("{message}");
+    std::process::exit(1);
+}
+
+/// Load the server certificate chain and key from `MEES_TLS_CERT` and `MEES_TLS_KEY`
+#[cfg(feature = "tls")]
+fn tls() -> Option<mees::tls::TlsServerConfig> {
+    let cert = std::fs::read(std::env::var("MEES_TLS_CERT").ok()?)
+        .unwrap_or_else(|e| exit(&format!("MEES_TLS_CERT: {e}")));
+    let key = std::fs::read(std::env::var("MEES_TLS_KEY").ok()?)
+        .unwrap_or_else(|e| exit(&format!("MEES_TLS_KEY: {e}")));
+    Some(
+        mees::tls::TlsServerConfig::from_pem(&cert, &key)
+            .unwrap_or_else(|e| exit(&format!("MEES_TLS_CERT and MEES_TLS_KEY: {e}"))),
+    )
 }
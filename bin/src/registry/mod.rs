@@ -1,42 +1,126 @@
-use std::{collections::HashMap, sync::atomic::AtomicU32};
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    sync::{atomic::AtomicU32, Arc},
+    time::Instant,
+};
 
-use mees::internals::{Control, Message, Payload};
-use tokio::sync::RwLock;
+use mees::{
+    internals::{
+        Control, Message, Payload, RequestAsk, RequestRegister, RequestResponse, RequestUnregister,
+        ResponseStatus, SchemaList, SchemaQuery, SessionClose, StreamItem,
+    },
+    transport::{FramePart, SkippedFrame},
+    Position, Schema,
+};
+use tokio::sync::{oneshot, Notify, RwLock};
 
-use crate::{action::Action, id::ConnectionID};
+use crate::{
+    action::Action,
+    id::ConnectionID,
+    log::{EventLog, Replay},
+    Config,
+};
+
+mod strategy;
+pub use strategy::Strategy;
+pub(crate) use strategy::{Registration, Xorshift};
+
+#[derive(Debug)]
+pub struct PendingRequest {
+    /// The id the caller used for the request
+    pub id: u32,
+    pub client: ConnectionID,
+    /// The handler the request was routed to, sessions stay pinned to it
+    pub handler: ConnectionID,
+    pub deadline: Option<Instant>,
+    /// The request as the caller sent it, kept while it can still be sent to another
+    /// handler if this one is lost
+    pub retry: Option<RequestAsk>,
+    /// How many times the request was sent to another handler
+    pub retries: u32,
+}
 
 #[derive(Debug)]
 pub struct Registry {
     events_subscribers: RwLock<HashMap<String, Vec<ConnectionID>>>,
-    request_handlers: RwLock<HashMap<String, Vec<ConnectionID>>>,
+    request_handlers: RwLock<HashMap<String, Vec<Registration>>>,
+    /// How many requests each path has received, the turn given to its strategy
     request_handlers_roundrobin: RwLock<HashMap<String, AtomicU32>>,
-    request_pending: RwLock<HashMap<u32, (u32, ConnectionID)>>,
+    /// The schemas the handlers of each path registered with, locked after `request_handlers`
+    schemas: RwLock<HashMap<String, Schema>>,
+    strategy: Strategy,
+    path_strategies: HashMap<String, Strategy>,
+    /// Picks handlers for `Strategy::Random`
+    random: Xorshift,
+    request_pending: RwLock<HashMap<u32, PendingRequest>>,
+    /// How many pending requests each handler has, kept along with `request_pending`
+    outstanding: std::sync::Mutex<HashMap<ConnectionID, usize>>,
+    /// The pending id of each request, by its caller and the caller's id
+    request_ids: RwLock<HashMap<(ConnectionID, u32), u32>>,
     request_count: AtomicU32,
+    /// How many times an idempotent request is sent to another handler
+    retry_budget: u32,
+    /// Woken whenever a request handler is registered
+    registered: Notify,
+    /// Woken whenever a connection subscribes to live events
+    subscribed: Notify,
+    /// Whether connections may announce themselves as peers, only once they proved they
+    /// know the secret
+    accept_peers: bool,
+    /// Connections to other brokers, in either direction
+    peers: RwLock<HashSet<ConnectionID>>,
+    /// Connections this broker opened to its peers, the paths handled here are announced on them
+    links: RwLock<HashSet<ConnectionID>>,
+    event_log: Option<Arc<EventLog>>,
+    /// Replays of the event log by subscriber and path, dropping the sender stops one
+    replays: RwLock<HashMap<(ConnectionID, String), oneshot::Sender<()>>>,
 }
 
 impl Registry {
-    pub fn new() -> Self {
-        Self {
+    /// Fails when the event log can not be opened
+    pub fn new(config: &Config) -> io::Result<Self> {
+        let event_log = match &config.event_log {
+            Some(config) => Some(Arc::new(EventLog::open(config.clone())?)),
+            None => None,
+        };
+        Ok(Self {
             events_subscribers: RwLock::new(HashMap::new()),
             request_handlers: RwLock::new(HashMap::new()),
             request_handlers_roundrobin: RwLock::new(HashMap::new()),
+            schemas: RwLock::new(HashMap::new()),
+            strategy: config.strategy,
+            path_strategies: config.path_strategies.clone(),
+            random: Xorshift::new(),
             request_pending: RwLock::new(HashMap::new()),
+            outstanding: std::sync::Mutex::new(HashMap::new()),
+            request_ids: RwLock::new(HashMap::new()),
             request_count: AtomicU32::new(0),
-        }
+            retry_budget: config.retry_budget,
+            registered: Notify::new(),
+            subscribed: Notify::new(),
+            accept_peers: config.secret.is_some(),
+            peers: RwLock::new(HashSet::new()),
+            links: RwLock::new(HashSet::new()),
+            event_log,
+            replays: RwLock::new(HashMap::new()),
+        })
     }
 
     pub async fn event_subscribe(&self, path: &str, id: ConnectionID) -> Result<(), String> {
         let selector = path.to_string();
-        self.events_subscribers
-            .write()
-            .await
-            .entry(selector)
-            .or_insert_with(Vec::new)
-            .push(id);
+        let mut events_subscribers = self.events_subscribers.write().await;
+        let entry = events_subscribers.entry(selector).or_insert_with(Vec::new);
+        if !entry.contains(&id) {
+            entry.push(id);
+        }
+        drop(events_subscribers);
+        self.subscribed.notify_waiters();
         Ok(())
     }
 
     pub async fn event_unsubscribe(&self, path: &str, id: ConnectionID) {
+        self.replays.write().await.remove(&(id, path.to_string()));
         let mut events_subscribers = self.events_subscribers.write().await;
         if let Some(entry) = events_subscribers.get_mut(path) {
             entry.retain(|&x| x != id);
@@ -48,27 +132,186 @@ impl Registry {
         events_subscribers.get(path).cloned().unwrap_or_default()
     }
 
-    pub async fn request_subscribe(&self, path: String, id: ConnectionID) {
-        self.request_handlers
-            .write()
+    /// Wait until at least `count` connections are subscribed to live events on `path`
+    pub async fn wait_subscribed(&self, path: &str, count: usize) {
+        loop {
+            let subscribed = self.subscribed.notified();
+            if self.event_subscribers(path).await.len() >= count {
+                return;
+            }
+            subscribed.await;
+        }
+    }
+
+    /// Subscribe through a replay of the event log from `from`, instead of live events
+    async fn event_replay(
+        &self,
+        path: String,
+        id: ConnectionID,
+        from: Position,
+    ) -> io::Result<Action> {
+        let Some(event_log) = &self.event_log else {
+            return Ok(Action::Ok);
+        };
+        let (topic, cursor) = event_log.cursor(path.clone(), from).await?;
+        self.event_unsubscribe(&path, id).await;
+        let (stop, stopped) = oneshot::channel();
+        // Replacing a replay stops it
+        self.replays.write().await.insert((id, path.clone()), stop);
+        Ok(Action::Replay(
+            id,
+            Replay {
+                topic,
+                cursor,
+                path,
+                stop: stopped,
+            },
+        ))
+    }
+
+    /// Remove the segments of the event log that are past retention
+    pub async fn retain_events(&self) {
+        if let Some(event_log) = &self.event_log {
+            let event_log = event_log.clone();
+            tokio::task::spawn_blocking(move || event_log.retain())
+                .await
+                .expect("event log retention panicked");
+        }
+    }
+
+    /// Register a handler, announcing the path to the peers when it is the first local one
+    pub async fn request_subscribe(&self, register: RequestRegister, id: ConnectionID) -> Action {
+        let RequestRegister {
+            path,
+            weight,
+            schema,
+            instance,
+        } = register;
+        let peer = self.peers.read().await.contains(&id);
+        let mut request_handlers = self.request_handlers.write().await;
+        if let Some(schema) = &schema {
+            self.schemas
+                .write()
+                .await
+                .insert(path.clone(), schema.clone());
+        }
+        let entry = request_handlers
+            .entry(path.clone())
+            .or_insert_with(Vec::new);
+        let first = !peer && entry.iter().all(|x| x.peer);
+        entry.push(Registration {
+            id,
+            weight: weight.max(1),
+            peer,
+            // Without a name the handler is only known by its connection
+            instance: match instance {
+                Some(instance) => mees::internals::fnv1a(instance.as_bytes()),
+                None => mees::internals::fnv1a(&id.0.to_le_bytes()),
+            },
+        });
+        self.registered.notify_waiters();
+        if first {
+            // Announced while the handlers are locked, so a new link sees it exactly once
+            self.announce(Payload::RequestRegister(RequestRegister {
+                path,
+                weight: 1,
+                schema,
+                instance: None,
+            }))
             .await
-            .entry(path)
-            .or_insert_with(Vec::new)
-            .push(id);
+        } else {
+            Action::Ok
+        }
     }
 
-    pub async fn request_unsubscribe(&self, path: &str, id: ConnectionID) {
+    /// Wait until at least `count` handlers are registered for `path`
+    pub async fn wait_registered(&self, path: &str, count: usize) {
+        loop {
+            let registered = self.registered.notified();
+            if self.request_subscribers(path).await.len() >= count {
+                return;
+            }
+            registered.await;
+        }
+    }
+
+    /// Unregister a handler, withdrawing the path from the peers when it was the last local one
+    pub async fn request_unsubscribe(&self, path: &str, id: ConnectionID) -> Action {
         let mut request_handlers = self.request_handlers.write().await;
         if let Some(entry) = request_handlers.get_mut(path) {
-            entry.retain(|&x| x != id);
+            if Self::withdraw(entry, id) {
+                return self
+                    .announce(Payload::RequestUnregister(RequestUnregister {
+                        path: path.to_string(),
+                    }))
+                    .await;
+            }
+        }
+        Action::Ok
+    }
+
+    /// Remove `id` from the handlers of a path, returning whether it was the last local one
+    fn withdraw(handlers: &mut Vec<Registration>, id: ConnectionID) -> bool {
+        let had_local = handlers.iter().any(|x| !x.peer);
+        handlers.retain(|x| x.id != id);
+        had_local && handlers.iter().all(|x| x.peer)
+    }
+
+    /// Send a message to every peer this broker is linked to
+    async fn announce(&self, payload: Payload) -> Action {
+        let links = self.links.read().await;
+        if links.is_empty() {
+            return Action::Ok;
         }
+        Action::Broadcast(links.iter().copied().collect(), Message { payload })
+    }
+
+    /// Mark a connection opened to a peer broker, returning the messages that introduce
+    /// this broker on it: the peer hello and the paths handled here
+    pub async fn add_link(&self, id: ConnectionID) -> Vec<Message> {
+        let request_handlers = self.request_handlers.read().await;
+        let schemas = self.schemas.read().await;
+        self.peers.write().await.insert(id);
+        self.links.write().await.insert(id);
+        let paths = request_handlers
+            .iter()
+            .filter(|(_, handlers)| handlers.iter().any(|x| !x.peer))
+            .map(|(path, _)| {
+                Message::new(Payload::RequestRegister(RequestRegister {
+                    path: path.clone(),
+                    weight: 1,
+                    schema: schemas.get(path).cloned(),
+                    instance: None,
+                }))
+            });
+        std::iter::once(Message::new(Payload::Control(Control::Peer)))
+            .chain(paths)
+            .collect()
+    }
+
+    /// The schemas of the paths with a handler, only those of requests named `name`
+    /// when it is given
+    pub async fn request_schemas(&self, name: Option<&str>) -> HashMap<String, Schema> {
+        let request_handlers = self.request_handlers.read().await;
+        let schemas = self.schemas.read().await;
+        schemas
+            .iter()
+            .filter(|(path, _)| {
+                request_handlers
+                    .get(*path)
+                    .is_some_and(|handlers| !handlers.is_empty())
+            })
+            .filter(|(path, _)| name.is_none() || path.rsplit_once('-').map(|(n, _)| n) == name)
+            .map(|(path, schema)| (path.clone(), schema.clone()))
+            .collect()
     }
 
-    pub async fn request_subscribers(&self, path: &str) -> Vec<ConnectionID> {
+    pub async fn request_subscribers(&self, path: &str) -> Vec<Registration> {
         let request_handlers = self.request_handlers.read().await;
         request_handlers.get(path).cloned().unwrap_or_default()
     }
 
+    #[allow(dead_code)]
     pub async fn is_active(&self, id: ConnectionID) -> bool {
         for (_, entry) in self.events_subscribers.read().await.iter() {
             if entry.contains(&id) {
@@ -76,87 +319,521 @@ impl Registry {
             }
         }
         for (_, entry) in self.request_handlers.read().await.iter() {
-            if entry.contains(&id) {
+            if entry.iter().any(|x| x.id == id) {
                 return true;
             }
         }
         false
     }
 
+    /// Forget a closed connection, failing the requests routed to it and cancelling
+    /// the ones it was still waiting on
+    pub async fn remove_connection(&self, id: ConnectionID) -> Vec<Action> {
+        let mut events_subscribers = self.events_subscribers.write().await;
+        for (_, entry) in events_subscribers.iter_mut() {
+            entry.retain(|&x| x != id);
+        }
+        drop(events_subscribers);
+        self.replays
+            .write()
+            .await
+            .retain(|(connection, _), _| *connection != id);
+        self.peers.write().await.remove(&id);
+        self.links.write().await.remove(&id);
+        let mut withdrawn = Vec::new();
+        let mut request_handlers = self.request_handlers.write().await;
+        for (path, entry) in request_handlers.iter_mut() {
+            if Self::withdraw(entry, id) {
+                withdrawn.push(
+                    self.announce(Payload::RequestUnregister(RequestUnregister {
+                        path: path.clone(),
+                    }))
+                    .await,
+                );
+            }
+        }
+        drop(request_handlers);
+        let mut request_pending = self.request_pending.write().await;
+        let lost = request_pending
+            .iter()
+            .filter(|(_, pending)| pending.client == id || pending.handler == id)
+            .map(|(pending_id, _)| *pending_id)
+            .collect::<Vec<_>>();
+        let mut request_ids = self.request_ids.write().await;
+        let lost = lost
+            .into_iter()
+            .filter_map(|pending_id| {
+                let pending = request_pending.remove(&pending_id)?;
+                self.settle(pending.handler);
+                request_ids.remove(&(pending.client, pending.id));
+                Some((pending_id, pending))
+            })
+            .collect::<Vec<_>>();
+        drop(request_ids);
+        drop(request_pending);
+        let mut actions = withdrawn;
+        for (pending_id, pending) in lost {
+            if pending.client == id {
+                // Nobody is waiting for the response anymore
+                if pending.handler != id {
+                    actions.push(Action::Send(
+                        pending.handler,
+                        Message {
+                            payload: Payload::Control(Control::Cancel(pending_id)),
+                        },
+                    ));
+                }
+            } else {
+                actions.push(self.retry(pending).await);
+            }
+        }
+        actions
+    }
+
+    /// Remove the pending requests whose deadline has passed, replying to their callers
+    /// and cancelling them on their handlers
+    pub async fn expire_pending(&self) -> Vec<Action> {
+        let now = Instant::now();
+        let mut request_pending = self.request_pending.write().await;
+        let expired = request_pending
+            .iter()
+            .filter(|(_, pending)| pending.deadline.is_some_and(|deadline| deadline <= now))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        let mut request_ids = self.request_ids.write().await;
+        let mut actions = Vec::new();
+        for id in expired {
+            let Some(pending) = request_pending.remove(&id) else {
+                continue;
+            };
+            self.settle(pending.handler);
+            request_ids.remove(&(pending.client, pending.id));
+            actions.push(Action::Send(
+                pending.client,
+                Message {
+                    payload: Payload::RequestResponse(RequestResponse {
+                        id: pending.id,
+                        status: ResponseStatus::Timeout,
+                        data: Vec::new(),
+                    }),
+                },
+            ));
+            actions.push(Action::Send(
+                pending.handler,
+                Message {
+                    payload: Payload::Control(Control::Cancel(id)),
+                },
+            ));
+        }
+        actions
+    }
+
+    /// Route a message from the caller of a session to the handler pinned for it
+    async fn session(
+        &self,
+        client: ConnectionID,
+        id: u32,
+        payload: impl FnOnce(u32) -> Payload,
+    ) -> Option<Action> {
+        let id = *self.request_ids.read().await.get(&(client, id))?;
+        let handler = self.request_pending.read().await.get(&id)?.handler;
+        Some(Action::Send(
+            handler,
+            Message {
+                payload: payload(id),
+            },
+        ))
+    }
+
+    /// Forget a request its caller gave up on and tell the handler to stop working on it
+    async fn cancel(&self, client: ConnectionID, id: u32) -> Option<Action> {
+        let id = self.request_ids.write().await.remove(&(client, id))?;
+        let pending = self.request_pending.write().await.remove(&id)?;
+        self.settle(pending.handler);
+        Some(Action::Send(
+            pending.handler,
+            Message {
+                payload: Payload::Control(Control::Cancel(id)),
+            },
+        ))
+    }
+
+    /// Answer for a frame that was too large to be read from a connection: a caller is
+    /// told its request or input was rejected, and the caller of a handler whose response
+    /// was rejected is told why while the handler is cancelled
+    pub async fn skipped(&self, connection: ConnectionID, skipped: SkippedFrame) -> Vec<Action> {
+        let rejected = |id| RequestResponse {
+            id,
+            status: ResponseStatus::FrameTooLarge(skipped.error),
+            data: Vec::new(),
+        };
+        match skipped.part {
+            FramePart::Request(id) => {
+                // The rest of a session's inputs can not be used without this one
+                let cancel = self.cancel(connection, id).await;
+                let reply = Action::Send(
+                    connection,
+                    Message::new(Payload::RequestResponse(rejected(id))),
+                );
+                cancel.into_iter().chain([reply]).collect()
+            }
+            FramePart::Response(id) => {
+                let Some(reply) = self
+                    .respond(connection, rejected(id), Payload::StreamEnd)
+                    .await
+                else {
+                    return Vec::new();
+                };
+                let cancel = Action::Send(
+                    connection,
+                    Message::new(Payload::Control(Control::Cancel(id))),
+                );
+                vec![reply, cancel]
+            }
+            FramePart::Other => Vec::new(),
+        }
+    }
+
+    /// Route the final response to a pending request back to its caller, only the
+    /// handler the request was sent to can answer it
+    async fn respond(
+        &self,
+        handler: ConnectionID,
+        response: RequestResponse,
+        payload: fn(RequestResponse) -> Payload,
+    ) -> Option<Action> {
+        let mut request_pending = self.request_pending.write().await;
+        // The request may have already expired, or been sent to another handler
+        if request_pending.get(&response.id)?.handler != handler {
+            return None;
+        }
+        let pending = request_pending.remove(&response.id)?;
+        drop(request_pending);
+        self.settle(pending.handler);
+        self.request_ids
+            .write()
+            .await
+            .remove(&(pending.client, pending.id));
+        let mut response = response;
+        response.id = pending.id;
+        Some(Action::Send(
+            pending.client,
+            Message {
+                payload: payload(response),
+            },
+        ))
+    }
+
+    /// Choose the handler for a request, `None` when there is nowhere to send it
+    async fn pick_handler(
+        &self,
+        client: ConnectionID,
+        request: &RequestAsk,
+    ) -> Option<ConnectionID> {
+        let subscribers = self.request_subscribers(&request.path).await;
+        let local = subscribers
+            .iter()
+            .filter(|x| !x.peer)
+            .copied()
+            .collect::<Vec<_>>();
+        // Requests are forwarded to a peer only without a local handler, and only
+        // once, so they can not go around in circles
+        let subscribers = if local.is_empty() && !self.peers.read().await.contains(&client) {
+            subscribers
+        } else {
+            local
+        };
+        if subscribers.is_empty() {
+            return None;
+        }
+        if let Some(key) = &request.route_key {
+            return Some(strategy::pick_by_key(&subscribers, key));
+        }
+        let turn = self
+            .request_handlers_roundrobin
+            .write()
+            .await
+            .entry(request.path.clone())
+            .or_insert_with(|| AtomicU32::new(0))
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        // Strategies are set by request name, so they outlive changes to the schema
+        let name = request
+            .path
+            .rsplit_once('-')
+            .map_or(request.path.as_str(), |(name, _)| name);
+        let strategy = self
+            .path_strategies
+            .get(name)
+            .copied()
+            .unwrap_or(self.strategy);
+        let outstanding = self.outstanding.lock().unwrap();
+        Some(strategy.pick(&subscribers, turn, &self.random, |id| {
+            outstanding.get(&id).copied().unwrap_or(0)
+        }))
+    }
+
+    /// Count a pending request of `handler` as done
+    fn settle(&self, handler: ConnectionID) {
+        let mut outstanding = self.outstanding.lock().unwrap();
+        if let Some(count) = outstanding.get_mut(&handler) {
+            *count -= 1;
+            if *count == 0 {
+                outstanding.remove(&handler);
+            }
+        }
+    }
+
+    /// Send a request to `handler` under a new id, keeping it pending until it is answered
+    async fn forward(
+        &self,
+        client: ConnectionID,
+        request: RequestAsk,
+        handler: ConnectionID,
+        deadline: Option<Instant>,
+        retries: u32,
+    ) -> Action {
+        // Sessions can not be replayed, their inputs have already gone to the lost handler
+        let retry = (request.idempotent && !request.session).then(|| request.clone());
+        let mut request_pending = self.request_pending.write().await;
+        let id = loop {
+            let id = self
+                .request_count
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            if !request_pending.contains_key(&id) {
+                break id;
+            }
+        };
+        *self.outstanding.lock().unwrap().entry(handler).or_insert(0) += 1;
+        request_pending.insert(
+            id,
+            PendingRequest {
+                id: request.id,
+                client,
+                handler,
+                deadline,
+                retry,
+                retries,
+            },
+        );
+        self.request_ids
+            .write()
+            .await
+            .insert((client, request.id), id);
+        let mut request = request;
+        request.id = id;
+        // The handler only gets the time left, not the caller's whole timeout again
+        request.timeout =
+            deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        Action::Send(
+            handler,
+            Message {
+                payload: Payload::RequestAsk(request),
+            },
+        )
+    }
+
+    /// Send a request whose handler was lost to another handler, if it is idempotent
+    /// and within the retry budget, or tell its caller it was lost
+    async fn retry(&self, pending: PendingRequest) -> Action {
+        if let Some(request) = pending
+            .retry
+            .filter(|_| pending.retries < self.retry_budget)
+        {
+            if let Some(handler) = self.pick_handler(pending.client, &request).await {
+                return self
+                    .forward(
+                        pending.client,
+                        request,
+                        handler,
+                        pending.deadline,
+                        pending.retries + 1,
+                    )
+                    .await;
+            }
+        }
+        Action::Send(
+            pending.client,
+            Message {
+                payload: Payload::RequestResponse(RequestResponse {
+                    id: pending.id,
+                    status: ResponseStatus::HandlerLost,
+                    data: Vec::new(),
+                }),
+            },
+        )
+    }
+
     pub async fn handle_message(&self, client: ConnectionID, msg: Message) -> Action {
         match msg.payload {
             Payload::Control(control) => match control {
-                Control::Ping => todo!(),
-                Control::Pong => todo!(),
-                Control::AuthPass(_) => todo!(),
-                Control::Disconnect => {
-                    let mut events_subscribers = self.events_subscribers.write().await;
-                    for (_, entry) in events_subscribers.iter_mut() {
-                        entry.retain(|&x| x != client);
-                    }
-                    let mut request_handlers = self.request_handlers.write().await;
-                    for (_, entry) in request_handlers.iter_mut() {
-                        entry.retain(|&x| x != client);
-                    }
+                Control::Ping => Action::Send(
+                    client,
+                    Message {
+                        payload: Payload::Control(Control::Pong),
+                    },
+                ),
+                Control::Pong => Action::Ok,
+                // Connections are authenticated before they reach the registry
+                Control::AuthPass(_) => Action::Send(
+                    client,
+                    Message {
+                        payload: Payload::Control(Control::AuthOk),
+                    },
+                ),
+                Control::AuthOk => Action::Ok,
+                // The connection is removed once it is closed
+                Control::Disconnect => Action::Ok,
+                Control::Cancel(id) => self.cancel(client, id).await.unwrap_or(Action::Ok),
+                Control::Peer if !self.accept_peers => {
+                    println!("Refused peer broker without a secret configured: {client:?}");
+                    Action::Ok
+                }
+                Control::Peer => {
+                    println!("Accepted peer broker: {client:?}");
+                    self.peers.write().await.insert(client);
                     Action::Ok
                 }
             },
             Payload::RequestRegister(register) => {
                 println!("Registering request handler: {:?}", register.path);
-                self.request_subscribe(register.path.clone(), client).await;
-                Action::Ok
+                self.request_subscribe(register, client).await
+            }
+            Payload::RequestUnregister(unregister) => {
+                self.request_unsubscribe(&unregister.path, client).await
             }
             Payload::RequestAsk(request) => {
-                let subscribers = self.request_subscribers(&request.path).await;
-                if subscribers.is_empty() {
-                    Action::Ok
-                } else {
-                    let handler = {
-                        let mut request_handlers_roundrobin =
-                            self.request_handlers_roundrobin.write().await;
-                        let index = request_handlers_roundrobin
-                            .entry(request.path.clone())
-                            .or_insert_with(|| AtomicU32::new(0))
-                            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                        subscribers[index as usize % subscribers.len()]
-                    };
-                    {
-                        let mut request_pending = self.request_pending.write().await;
-                        let id = loop {
-                            let id = self
-                                .request_count
-                                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                            if !request_pending.contains_key(&id) {
-                                break id;
-                            }
+                let deadline = request.timeout.map(|timeout| Instant::now() + timeout);
+                match self.pick_handler(client, &request).await {
+                    Some(handler) => self.forward(client, request, handler, deadline, 0).await,
+                    None => {
+                        // Handlers of another version of the request tell the caller why
+                        // nothing answers it
+                        let name = request.path.rsplit_once('-').map(|(name, _)| name);
+                        let schemas = match name {
+                            Some(name) => self.request_schemas(Some(name)).await,
+                            None => HashMap::new(),
+                        };
+                        let status = if schemas.is_empty() {
+                            ResponseStatus::NoHandler
+                        } else {
+                            ResponseStatus::SchemaMismatch(schemas.into_values().collect())
                         };
-                        request_pending.insert(id, (request.id, client));
-                        let mut request = request;
-                        request.id = id;
                         Action::Send(
-                            handler,
+                            client,
                             Message {
-                                payload: Payload::RequestAsk(request),
+                                payload: Payload::RequestResponse(RequestResponse {
+                                    id: request.id,
+                                    status,
+                                    data: Vec::new(),
+                                }),
                             },
                         )
                     }
                 }
             }
-            Payload::RequestResponse(response) => {
+            Payload::EventSubscribe(subscribe) => {
+                println!("Subscribing to event: {:?}", subscribe.path);
+                if self.event_log.is_some() {
+                    if let Some(from) = subscribe.from {
+                        return self
+                            .event_replay(subscribe.path, client, from)
+                            .await
+                            .unwrap_or_else(|e| {
+                                println!("Failed to replay events: {e}");
+                                Action::Ok
+                            });
+                    }
+                }
+                // A live subscription replaces a replay
+                self.replays
+                    .write()
+                    .await
+                    .remove(&(client, subscribe.path.clone()));
+                if let Err(e) = self.event_subscribe(&subscribe.path, client).await {
+                    println!("Failed to subscribe to event: {e}");
+                }
+                Action::Ok
+            }
+            Payload::EventUnsubscribe(unsubscribe) => {
+                self.event_unsubscribe(&unsubscribe.path, client).await;
+                Action::Ok
+            }
+            Payload::EventPublish(mut event) => {
+                // Replays pick the event up from the log
+                if let Some(event_log) = &self.event_log {
+                    let (offset, data) = event_log
+                        .append(event.path.clone(), std::mem::take(&mut event.data))
+                        .await;
+                    event.data = data;
+                    match offset {
+                        Ok(offset) => event.offset = Some(offset),
+                        Err(e) => println!("Failed to log event: {e}"),
+                    }
+                }
+                let subscribers = self.event_subscribers(&event.path).await;
+                if subscribers.is_empty() {
+                    Action::Ok
+                } else {
+                    Action::Broadcast(
+                        subscribers,
+                        Message {
+                            payload: Payload::EventPublish(event),
+                        },
+                    )
+                }
+            }
+            Payload::RequestResponse(response) => self
+                .respond(client, response, Payload::RequestResponse)
+                .await
+                .unwrap_or(Action::Ok),
+            Payload::StreamEnd(response) => self
+                .respond(client, response, Payload::StreamEnd)
+                .await
+                .unwrap_or(Action::Ok),
+            Payload::SessionSend(item) => self
+                .session(client, item.id, |id| {
+                    Payload::SessionSend(StreamItem {
+                        id,
+                        data: item.data,
+                    })
+                })
+                .await
+                .unwrap_or(Action::Ok),
+            Payload::SessionClose(close) => self
+                .session(client, close.id, |id| {
+                    Payload::SessionClose(SessionClose { id })
+                })
+                .await
+                .unwrap_or(Action::Ok),
+            Payload::StreamItem(item) => {
                 let mut request_pending = self.request_pending.write().await;
-                if let Some((id, client)) = request_pending.remove(&response.id) {
-                    let mut response = response;
-                    response.id = id;
+                // The stream stays pending until it ends
+                if let Some(pending) = request_pending
+                    .get_mut(&item.id)
+                    .filter(|pending| pending.handler == client)
+                {
+                    // Once the caller has items, retrying would repeat them
+                    pending.retry = None;
+                    let mut item = item;
+                    item.id = pending.id;
                     Action::Send(
-                        client,
+                        pending.client,
                         Message {
-                            payload: Payload::RequestResponse(response),
+                            payload: Payload::StreamItem(item),
                         },
                     )
                 } else {
-                    todo!()
+                    Action::Ok
                 }
             }
+            Payload::SchemaQuery(SchemaQuery { id, name }) => Action::Send(
+                client,
+                Message::new(Payload::SchemaList(SchemaList {
+                    id,
+                    schemas: self.request_schemas(name.as_deref()).await,
+                })),
+            ),
+            Payload::SchemaList(_) => Action::Ok,
         }
     }
 }
@@ -0,0 +1,229 @@
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::id::ConnectionID;
+
+/// A handler registered for a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Registration {
+    pub id: ConnectionID,
+    /// The share of requests given to this handler by [`Strategy::Weighted`], at least 1
+    pub weight: u32,
+    /// The handler is another broker, which only gets requests without a local handler
+    pub peer: bool,
+    /// The hash of the responder's instance name, which routing keys are hashed with
+    pub instance: u64,
+}
+
+/// How requests are spread over the handlers registered for a path
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Each handler in turn
+    #[default]
+    RoundRobin,
+    /// The handler with the fewest pending requests
+    LeastOutstanding,
+    /// Any handler, chosen at random
+    Random,
+    /// Each handler in turn, as many times in a row as its registered weight
+    Weighted,
+}
+
+impl Strategy {
+    /// Pick the handler for the `turn`th request to a path, `outstanding` counts the
+    /// pending requests of a handler
+    ///
+    /// `handlers` must not be empty
+    pub(crate) fn pick(
+        self,
+        handlers: &[Registration],
+        turn: u32,
+        random: &Xorshift,
+        outstanding: impl Fn(ConnectionID) -> usize,
+    ) -> ConnectionID {
+        let len = handlers.len();
+        match self {
+            Self::RoundRobin => handlers[turn as usize % len].id,
+            // Ties are broken in turn, so idle handlers all get used
+            Self::LeastOutstanding => (0..len)
+                .map(|i| handlers[(turn as usize + i) % len].id)
+                .min_by_key(|&id| outstanding(id))
+                .expect("handlers is not empty"),
+            Self::Random => handlers[(random.next() % len as u64) as usize].id,
+            Self::Weighted => {
+                let total = handlers.iter().map(|h| u64::from(h.weight)).sum::<u64>();
+                let mut point = u64::from(turn) % total;
+                for handler in handlers {
+                    if point < u64::from(handler.weight) {
+                        return handler.id;
+                    }
+                    point -= u64::from(handler.weight);
+                }
+                unreachable!("the point is below the total weight")
+            }
+        }
+    }
+}
+
+/// A xorshift generator, random enough to spread requests over handlers
+#[derive(Debug)]
+pub(crate) struct Xorshift(AtomicU64);
+
+impl Xorshift {
+    pub fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_nanos() as u64);
+        // A state of 0 would stay 0
+        Self(AtomicU64::new(seed | 1))
+    }
+
+    pub fn next(&self) -> u64 {
+        let step = |mut x: u64| {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+        let previous = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| Some(step(x)))
+            .expect("the update always succeeds");
+        step(previous)
+    }
+}
+
+/// Pick the handler for a routing key by rendezvous hashing, so when a handler joins or
+/// leaves only the keys it gains or loses are moved
+///
+/// `handlers` must not be empty
+pub(crate) fn pick_by_key(handlers: &[Registration], key: &[u8]) -> ConnectionID {
+    // Hashed the same on every broker and release, so federated brokers agree
+    let mut bytes = key.to_vec();
+    handlers
+        .iter()
+        .max_by_key(|handler| {
+            bytes.truncate(key.len());
+            bytes.extend_from_slice(&handler.instance.to_le_bytes());
+            mees::internals::fnv1a(&bytes)
+        })
+        .expect("handlers is not empty")
+        .id
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "round-robin" => Ok(Self::RoundRobin),
+            "least-outstanding" => Ok(Self::LeastOutstanding),
+            "random" => Ok(Self::Random),
+            "weighted" => Ok(Self::Weighted),
+            _ => Err(format!("unknown load balancing strategy: {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handlers(weights: &[u32]) -> Vec<Registration> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, &weight)| Registration {
+                id: ConnectionID(i as u32),
+                weight,
+                peer: false,
+                instance: i as u64,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_robin() {
+        let handlers = handlers(&[1, 1, 1]);
+        let picks = (0..6)
+            .map(|turn| {
+                Strategy::RoundRobin
+                    .pick(&handlers, turn, &Xorshift::new(), |_| 0)
+                    .0
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(picks, [0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn least_outstanding() {
+        let handlers = handlers(&[1, 1, 1]);
+        let outstanding = |id: ConnectionID| [3, 0, 1][id.0 as usize];
+        for turn in 0..3 {
+            assert_eq!(
+                Strategy::LeastOutstanding.pick(&handlers, turn, &Xorshift::new(), outstanding),
+                ConnectionID(1)
+            );
+        }
+    }
+
+    #[test]
+    fn weighted() {
+        let handlers = handlers(&[3, 1]);
+        let picks = (0..8)
+            .map(|turn| {
+                Strategy::Weighted
+                    .pick(&handlers, turn, &Xorshift::new(), |_| 0)
+                    .0
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(picks, [0, 0, 0, 1, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn by_key() {
+        let keys = (0..100u32).map(|key| key.to_be_bytes()).collect::<Vec<_>>();
+        let before = handlers(&[1, 1, 1]);
+        let after = handlers(&[1, 1, 1, 1]);
+        for key in &keys {
+            let picked = pick_by_key(&before, key);
+            assert_eq!(picked, pick_by_key(&before, key));
+            // Adding a handler only moves keys to the new handler
+            let moved = pick_by_key(&after, key);
+            assert!(moved == picked || moved == ConnectionID(3));
+        }
+    }
+
+    #[test]
+    fn by_key_across_reconnects() {
+        let before = handlers(&[1, 1, 1]);
+        // The same responders on new connections
+        let after = before
+            .iter()
+            .map(|handler| Registration {
+                id: ConnectionID(handler.id.0 + 10),
+                ..*handler
+            })
+            .collect::<Vec<_>>();
+        for key in 0..100u32 {
+            let key = key.to_be_bytes();
+            assert_eq!(
+                pick_by_key(&after, &key).0,
+                pick_by_key(&before, &key).0 + 10
+            );
+        }
+    }
+
+    #[test]
+    fn random() {
+        let handlers = handlers(&[1, 1, 1]);
+        let random = Xorshift::new();
+        let picks = (0..100)
+            .map(|turn| Strategy::Random.pick(&handlers, turn, &random, |_| 0).0)
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(picks.len(), 3);
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use mees::{
+    internals::BoxStream,
+    transport::{memory, Listener, MemoryListener},
+    Requestable,
+};
+
+/// A listener whose first accepts fail, like one out of file descriptors
+struct Exhausted {
+    inner: MemoryListener,
+    failures: AtomicU32,
+}
+
+#[mees::async_trait::async_trait]
+impl Listener for Exhausted {
+    async fn accept(&self) -> std::io::Result<BoxStream> {
+        if self
+            .failures
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+            .is_ok()
+        {
+            return Err(std::io::Error::other("too many open files"));
+        }
+        self.inner.accept().await
+    }
+}
+
+#[tokio::test]
+pub async fn accept_errors() {
+    mees::requests! {
+        Add (i32, i32) -> i32
+    };
+
+    let (transport, listener) = memory();
+    tokio::spawn(mees_bin::run_with_listener(
+        Exhausted {
+            inner: listener,
+            failures: AtomicU32::new(2),
+        },
+        mees_bin::Config::default(),
+    ));
+
+    // The broker keeps accepting after the failures
+    let client = mees::Client::with_transport(transport, mees::ClientOptions::default())
+        .await
+        .unwrap();
+    assert!(matches!(
+        Add(1, 2).request(&client).await,
+        Err(mees::RequestError::NoHandler { .. })
+    ));
+}
@@ -6,18 +6,13 @@ pub async fn add() {
         Add (i32, i32) -> i32
     };
 
+    let broker = mees_bin::Embedded::start();
+
     let mut responder = mees::Responder::new();
     responder.register(Add::handler(|add| async move { add.0 + add.1 }));
-    tokio::spawn(async move {
-        responder.run("localhost:6454").await.unwrap();
-    });
-
-    tokio::spawn(async move {
-        mees_bin::run("localhost:6454").await;
-    });
+    broker.responder(responder).await;
 
-    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
-    let client = mees::Client::new("localhost:6454").await.unwrap();
+    let client = broker.client().await.unwrap();
     let res = Add(1, 2).request(&client).await.unwrap();
     assert_eq!(res, 3);
 }
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+use mees::{ClientOptions, Requestable, ResponderOptions};
+
+mees::requests! {
+    Add (i32, i32) -> i32
+}
+
+fn responder(secret: &str) -> mees::Responder {
+    let mut responder = mees::Responder::with_options(ResponderOptions {
+        auth: Some(secret.to_string()),
+        ..ResponderOptions::default()
+    });
+    responder.register(Add::handler(|add| async move { add.0 + add.1 }));
+    responder
+}
+
+async fn client(
+    broker: &mees_bin::Embedded,
+    secret: &str,
+) -> Result<mees::Client, Box<dyn std::error::Error>> {
+    broker
+        .client_with_options(ClientOptions {
+            auth: Some(secret.to_string()),
+            ..ClientOptions::default()
+        })
+        .await
+}
+
+#[tokio::test]
+pub async fn auth() {
+    let broker = mees_bin::Embedded::with_config(mees_bin::Config {
+        secret: Some("hunter2".to_string()),
+        ..mees_bin::Config::default()
+    })
+    .unwrap();
+
+    assert!(client(&broker, "wrong").await.is_err());
+    assert!(responder("wrong")
+        .run_with_transport(&broker.transport())
+        .await
+        .is_err());
+    broker.responder(responder("hunter2")).await;
+
+    // Connections that skip the handshake are closed
+    let client_without_auth = broker.client().await.unwrap();
+    let res = Add(1, 2)
+        .request_timeout(&client_without_auth, Duration::from_millis(500))
+        .await;
+    assert!(matches!(res, Err(mees::RequestError::ConnectionLost)));
+
+    let client = client(&broker, "hunter2").await.unwrap();
+    let res = Add(1, 2).request(&client).await.unwrap();
+    assert_eq!(res, 3);
+}
@@ -0,0 +1,83 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use mees::{Requestable, Streamable};
+
+static ABORTED: AtomicU32 = AtomicU32::new(0);
+
+/// Counts the handler futures dropped while holding it
+struct Aborted;
+
+impl Drop for Aborted {
+    fn drop(&mut self) {
+        ABORTED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+async fn wait_aborted(count: u32) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while ABORTED.load(Ordering::Relaxed) < count {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+#[tokio::test]
+pub async fn cancel() {
+    mees::requests! {
+        Slow (i32) -> i32
+        Fast (i32) -> i32
+        Forever (i32) -> stream i32
+    };
+
+    let broker = mees_bin::Embedded::start();
+
+    let mut responder = mees::Responder::new();
+    responder.register(Slow::handler(|slow| {
+        let aborted = Aborted;
+        async move {
+            let _aborted = aborted;
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            slow.0
+        }
+    }));
+    responder.register(Fast::handler(|fast| async move { fast.0 }));
+    responder.register(Forever::handler(|forever| {
+        futures::stream::unfold(Aborted, move |aborted| async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            Some((forever.0, aborted))
+        })
+    }));
+    broker.responder(responder).await;
+
+    let client = broker.client().await.unwrap();
+
+    // Dropping the request aborts the handler, so the next request is not held up
+    let slow = tokio::time::timeout(Duration::from_millis(100), Slow(1).request(&client)).await;
+    assert!(slow.is_err());
+    wait_aborted(1).await;
+    let fast = tokio::time::timeout(Duration::from_secs(5), Fast(2).request(&client)).await;
+    assert_eq!(fast.unwrap().unwrap(), 2);
+
+    let mut forever = Forever(3).request(&client).await.unwrap();
+    assert_eq!(forever.next().await.unwrap().unwrap(), 3);
+    drop(forever);
+    wait_aborted(2).await;
+    let fast = tokio::time::timeout(Duration::from_secs(5), Fast(4).request(&client)).await;
+    assert_eq!(fast.unwrap().unwrap(), 4);
+
+    // So does dropping a request made with a timeout, long before it runs out
+    let slow = tokio::time::timeout(
+        Duration::from_millis(100),
+        Slow(5).request_timeout(&client, Duration::from_secs(60)),
+    )
+    .await;
+    assert!(slow.is_err());
+    wait_aborted(3).await;
+    let fast = tokio::time::timeout(Duration::from_secs(5), Fast(6).request(&client)).await;
+    assert_eq!(fast.unwrap().unwrap(), 6);
+}
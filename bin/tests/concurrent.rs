@@ -0,0 +1,126 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use mees::{Requestable, ResponderOptions};
+use tokio::sync::Semaphore;
+
+mees::requests! {
+    Block () -> ()
+    Fast (u32) -> u32
+}
+
+/// Handlers that tell when they start and then wait to be let through
+struct Gate {
+    started: Semaphore,
+    open: Semaphore,
+    running: AtomicU32,
+    most_running: AtomicU32,
+    handled: AtomicU32,
+}
+
+impl Gate {
+    const fn new() -> Self {
+        Self {
+            started: Semaphore::const_new(0),
+            open: Semaphore::const_new(0),
+            running: AtomicU32::new(0),
+            most_running: AtomicU32::new(0),
+            handled: AtomicU32::new(0),
+        }
+    }
+
+    async fn pass(&self) {
+        self.handled.fetch_add(1, Ordering::Relaxed);
+        let running = self.running.fetch_add(1, Ordering::Relaxed) + 1;
+        self.most_running.fetch_max(running, Ordering::Relaxed);
+        self.started.add_permits(1);
+        self.open.acquire().await.unwrap().forget();
+        self.running.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Wait until `count` more handlers have started
+    async fn started(&self, count: u32) {
+        self.started.acquire_many(count).await.unwrap().forget();
+    }
+}
+
+async fn broker(max_in_flight: usize, gate: &'static Gate) -> mees_bin::Embedded {
+    let broker = mees_bin::Embedded::start();
+    let mut responder = mees::Responder::with_options(ResponderOptions {
+        max_in_flight,
+        ..ResponderOptions::default()
+    });
+    responder.register(Block::handler(move |_| gate.pass()));
+    responder.register(Fast::handler(|fast| async move { fast.0 }));
+    broker.responder(responder).await;
+    broker
+}
+
+#[tokio::test]
+pub async fn concurrent() {
+    static GATE: Gate = Gate::new();
+    let broker = broker(4, &GATE).await;
+    let client = broker.client().await.unwrap();
+
+    // A blocked request does not hold up the ones behind it
+    let blocked = Block().request(&client);
+    tokio::pin!(blocked);
+    tokio::select! {
+        _ = &mut blocked => panic!("the blocked request was answered"),
+        () = GATE.started(1) => {}
+    }
+    tokio::select! {
+        _ = &mut blocked => panic!("the blocked request was answered"),
+        fast = Fast(1).request(&client) => assert_eq!(fast.unwrap(), 1),
+    }
+    GATE.open.add_permits(1);
+    blocked.await.unwrap();
+}
+
+#[tokio::test]
+pub async fn max_in_flight() {
+    static GATE: Gate = Gate::new();
+    let broker = broker(4, &GATE).await;
+    let client = broker.client().await.unwrap();
+
+    // Only `max_in_flight` requests are handled at once
+    let requests = (0..8).map(|_| Block()).collect::<Vec<_>>();
+    let responses = futures::future::join_all(requests.iter().map(|block| block.request(&client)));
+    tokio::pin!(responses);
+    tokio::select! {
+        _ = &mut responses => panic!("the blocked requests were answered"),
+        () = GATE.started(4) => {}
+    }
+    GATE.open.add_permits(8);
+    assert!(responses.await.into_iter().all(|response| response.is_ok()));
+    assert_eq!(GATE.handled.load(Ordering::Relaxed), 8);
+    assert_eq!(GATE.most_running.load(Ordering::Relaxed), 4);
+}
+
+#[tokio::test]
+pub async fn queued_timeout() {
+    static GATE: Gate = Gate::new();
+    let broker = broker(1, &GATE).await;
+    let client = broker.client().await.unwrap();
+
+    let blocked = Block().request(&client);
+    tokio::pin!(blocked);
+    tokio::select! {
+        _ = &mut blocked => panic!("the blocked request was answered"),
+        () = GATE.started(1) => {}
+    }
+    // The timeout runs out while the request waits behind the blocked one
+    let queued = Block()
+        .request_timeout(&client, Duration::from_millis(200))
+        .await;
+    assert!(matches!(queued, Err(mees::RequestError::Timeout)));
+    GATE.open.add_permits(1);
+    blocked.await.unwrap();
+
+    // Requests are taken from the queue in order, so this one follows the timed out one,
+    // which was never handled
+    assert_eq!(Fast(1).request(&client).await.unwrap(), 1);
+    assert_eq!(GATE.handled.load(Ordering::Relaxed), 1);
+}
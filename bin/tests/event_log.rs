@@ -0,0 +1,83 @@
+use std::time::{Duration, SystemTime};
+
+use mees::{Eventable, Position};
+use mees_bin::{Config, LogConfig};
+
+mees::events! {
+    Tick (u64)
+}
+
+fn config(dir: &std::path::Path) -> Config {
+    Config {
+        event_log: Some(LogConfig {
+            segment_size: 64,
+            ..LogConfig::new(dir)
+        }),
+        ..Config::default()
+    }
+}
+
+async fn recv(subscription: &mut mees::Subscription<Tick>) -> (u64, Option<u64>) {
+    let (tick, offset) =
+        tokio::time::timeout(Duration::from_secs(5), subscription.recv_with_offset())
+            .await
+            .unwrap()
+            .unwrap();
+    (tick.0, offset)
+}
+
+#[tokio::test]
+pub async fn event_log() {
+    let dir = std::env::temp_dir().join(format!("mees-event-log-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let broker = mees_bin::Embedded::with_config(config(&dir)).unwrap();
+    let client = broker.client().await.unwrap();
+    for i in 0..5 {
+        Tick(i).publish(&client).await.unwrap();
+    }
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let since = SystemTime::now();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    for i in 5..8 {
+        Tick(i).publish(&client).await.unwrap();
+    }
+
+    // Events published while nobody was subscribed are replayed, then new ones follow
+    let mut ticks = client.subscribe_from::<Tick>(Position::Offset(2)).await;
+    for i in 2..8 {
+        assert_eq!(recv(&mut ticks).await, (i, Some(i)));
+    }
+    Tick(8).publish(&client).await.unwrap();
+    assert_eq!(recv(&mut ticks).await, (8, Some(8)));
+
+    let other = broker.client().await.unwrap();
+    let mut recent = other.subscribe_from::<Tick>(Position::Time(since)).await;
+    for i in 5..9 {
+        assert_eq!(recv(&mut recent).await, (i, Some(i)));
+    }
+    drop((client, other, broker));
+
+    // The log outlives the broker
+    let broker = mees_bin::Embedded::with_config(config(&dir)).unwrap();
+    let client = broker.client().await.unwrap();
+    let mut ticks = client.subscribe_from::<Tick>(Position::Offset(0)).await;
+    for i in 0..9 {
+        assert_eq!(recv(&mut ticks).await, (i, Some(i)));
+    }
+    Tick(9).publish(&client).await.unwrap();
+    assert_eq!(recv(&mut ticks).await, (9, Some(9)));
+    drop((client, broker));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+pub async fn unopenable_event_log() {
+    // A file where the log directory should be
+    let dir = std::env::temp_dir().join(format!("mees-event-log-file-{}", std::process::id()));
+    std::fs::write(&dir, b"").unwrap();
+
+    assert!(mees_bin::Embedded::with_config(config(&dir)).is_err());
+    std::fs::remove_file(&dir).unwrap();
+}
@@ -0,0 +1,62 @@
+use mees::Eventable;
+
+#[tokio::test]
+pub async fn events() {
+    mees::events! {
+        Greeting { name: String }
+        Tick (u64)
+    };
+
+    let broker = mees_bin::Embedded::start();
+    let subscriber = broker.client().await.unwrap();
+    let mut greetings = subscriber.subscribe::<Greeting>().await;
+    let mut ticks = subscriber.subscribe::<Tick>().await;
+    broker.wait_subscribed(Greeting::path(), 1).await;
+    broker.wait_subscribed(Tick::path(), 1).await;
+
+    let publisher = broker.client().await.unwrap();
+    Tick(1).publish(&publisher).await.unwrap();
+    Greeting {
+        name: "world".to_string(),
+    }
+    .publish(&publisher)
+    .await
+    .unwrap();
+    Tick(2).publish(&publisher).await.unwrap();
+
+    assert_eq!(greetings.recv().await.unwrap().name, "world");
+    assert_eq!(ticks.recv().await.unwrap().0, 1);
+    assert_eq!(ticks.recv().await.unwrap().0, 2);
+}
+
+#[tokio::test]
+pub async fn malformed_event() {
+    mees::events! {
+        Tick (u64)
+    };
+
+    /// Published on the path of `Tick` without being one
+    #[derive(serde::Serialize, serde::Deserialize)]
+    struct Forged(String);
+
+    impl Eventable for Forged {
+        fn path() -> &'static str {
+            Tick::path()
+        }
+    }
+
+    let broker = mees_bin::Embedded::start();
+    let subscriber = broker.client().await.unwrap();
+    let mut ticks = subscriber.subscribe::<Tick>().await;
+    let publisher = broker.client().await.unwrap();
+    broker.wait_subscribed(Tick::path(), 1).await;
+
+    Forged("not a tick".to_string())
+        .publish(&publisher)
+        .await
+        .unwrap();
+    Tick(1).publish(&publisher).await.unwrap();
+
+    // The event that can not be decoded is skipped
+    assert_eq!(ticks.recv().await.unwrap().0, 1);
+}
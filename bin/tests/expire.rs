@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use mees::{
+    internals::{Control, Message, Payload, RequestRegister},
+    transport::{Connection, Transport},
+    Requestable,
+};
+
+#[tokio::test]
+pub async fn expired_request_is_cancelled() {
+    mees::requests! {
+        Add (i32, i32) -> i32
+    };
+
+    let broker = mees_bin::Embedded::start();
+
+    // A handler that never answers, but reports what the broker sends it
+    let mut handler = Connection::new(broker.transport().connect().await.unwrap());
+    handler
+        .write(&Message::new(Payload::RequestRegister(RequestRegister {
+            path: Add::path().to_string(),
+            weight: 1,
+            schema: None,
+            instance: None,
+        })))
+        .await
+        .unwrap();
+    broker.wait_registered(Add::path(), 1).await;
+
+    let client = broker.client().await.unwrap();
+    let timeout = Duration::from_millis(300);
+    let (res, ()) = tokio::join!(Add(1, 2).request_timeout(&client, timeout), async {
+        let Some(Message {
+            payload: Payload::RequestAsk(request),
+        }) = handler.read().await.unwrap()
+        else {
+            panic!("expected a request");
+        };
+        assert!(request.timeout.is_some_and(|t| t <= timeout));
+        // The broker cancels the request on the handler once it expires
+        loop {
+            let message = tokio::time::timeout(Duration::from_secs(5), handler.read())
+                .await
+                .expect("the request was not cancelled")
+                .unwrap()
+                .unwrap();
+            if let Payload::Control(Control::Cancel(id)) = message.payload {
+                assert_eq!(id, request.id);
+                break;
+            }
+        }
+    });
+    assert!(matches!(res, Err(mees::RequestError::Timeout)));
+}
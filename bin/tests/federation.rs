@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use mees::{
+    internals::{Control, Message, Payload, RequestRegister},
+    transport::{memory, Connection, MemoryTransport, Transport},
+    ClientOptions, RequestError, Requestable, ResponderOptions, Streamable,
+};
+
+const SECRET: &str = "federation";
+
+mees::requests! {
+    Add (i32, i32) -> i32
+    Count (i32) -> stream i32
+}
+
+/// Retry `f` until it returns `Some`, while the brokers link up
+async fn eventually<T, F, Fut>(f: F) -> T
+where
+    F: Fn() -> Fut,
+    Fut: std::future::Future<Output = Option<T>>,
+{
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(value) = f().await {
+                return value;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+pub async fn federation() {
+    let (brokers, listeners): (Vec<_>, Vec<_>) = (0..3).map(|_| memory()).unzip();
+    for (index, listener) in listeners.into_iter().enumerate() {
+        let config = mees_bin::Config {
+            secret: Some(SECRET.to_string()),
+            peers: brokers
+                .iter()
+                .enumerate()
+                .filter(|(peer, _)| *peer != index)
+                .map(|(peer, transport)| mees_bin::Peer::new(peer.to_string(), transport.clone()))
+                .collect(),
+            ..mees_bin::Config::default()
+        };
+        tokio::spawn(mees_bin::run_with_listener(listener, config));
+    }
+    let options = ResponderOptions {
+        auth: Some(SECRET.to_string()),
+        ..ResponderOptions::default()
+    };
+
+    let mut adder = mees::Responder::with_options(options.clone());
+    adder.register(Add::handler(|add| async move { add.0 + add.1 }));
+    let transport = brokers[0].clone();
+    let adder = tokio::spawn(async move {
+        let _ = adder.run_with_transport(&transport).await;
+    });
+    let mut counter = mees::Responder::with_options(options);
+    counter.register(Count::handler(|count| futures::stream::iter(0..count.0)));
+    let transport = brokers[1].clone();
+    tokio::spawn(async move {
+        let _ = counter.run_with_transport(&transport).await;
+    });
+
+    let client = |broker: &MemoryTransport| {
+        mees::Client::with_transport(
+            broker.clone(),
+            ClientOptions {
+                auth: Some(SECRET.to_string()),
+                ..ClientOptions::default()
+            },
+        )
+    };
+    let third = client(&brokers[2]).await.unwrap();
+    let sum = eventually(|| async { Add(1, 2).request(&third).await.ok() }).await;
+    assert_eq!(sum, 3);
+
+    // Streams are routed back through the peer as well
+    let first = client(&brokers[0]).await.unwrap();
+    let items = eventually(|| async {
+        let mut count = Count(3).request(&first).await.ok()?;
+        let mut items = Vec::new();
+        while let Some(item) = count.next().await {
+            items.push(item.ok()?);
+        }
+        Some(items)
+    })
+    .await;
+    assert_eq!(items, [0, 1, 2]);
+
+    // Once the only handler is gone, the request fails through the peers too
+    adder.abort();
+    eventually(|| async {
+        matches!(
+            Add(1, 2).request(&third).await,
+            Err(RequestError::NoHandler { .. })
+        )
+        .then_some(())
+    })
+    .await;
+}
+
+#[tokio::test]
+pub async fn peer_transport() {
+    let first = mees_bin::Embedded::with_config(mees_bin::Config {
+        secret: Some("first".to_string()),
+        ..mees_bin::Config::default()
+    })
+    .unwrap();
+    // Linked through the first broker's own transport and secret
+    let second = mees_bin::Embedded::with_config(mees_bin::Config {
+        secret: Some("second".to_string()),
+        peers: vec![mees_bin::Peer::new("first", first.transport()).with_secret("first")],
+        ..mees_bin::Config::default()
+    })
+    .unwrap();
+
+    let mut adder = mees::Responder::with_options(mees::ResponderOptions {
+        auth: Some("second".to_string()),
+        ..mees::ResponderOptions::default()
+    });
+    adder.register(Add::handler(|add| async move { add.0 + add.1 }));
+    second.responder(adder).await;
+    first.wait_registered(Add::path(), 1).await;
+
+    let client = first
+        .client_with_options(mees::ClientOptions {
+            auth: Some("first".to_string()),
+            ..mees::ClientOptions::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(Add(1, 2).request(&client).await.unwrap(), 3);
+}
+
+#[tokio::test]
+pub async fn unauthenticated_peer() {
+    // Without a secret nothing can vouch for a peer
+    let broker = mees_bin::Embedded::start();
+    let mut impostor = Connection::new(broker.transport().connect().await.unwrap());
+    for payload in [
+        Payload::Control(Control::Peer),
+        Payload::RequestRegister(RequestRegister {
+            path: Add::path().to_string(),
+            weight: 1,
+            schema: None,
+            instance: None,
+        }),
+    ] {
+        impostor.write(&Message::new(payload)).await.unwrap();
+    }
+    broker.wait_registered(Add::path(), 1).await;
+    let mut adder = mees::Responder::new();
+    adder.register(Add::handler(|add| async move { add.0 + add.1 }));
+    broker.responder(adder).await;
+
+    // A peer only gets requests without a local handler, the impostor is just another handler
+    let client = broker.client().await.unwrap();
+    let requests = tokio::spawn(async move {
+        for _ in 0..2 {
+            let _ = Add(1, 2)
+                .request_timeout(&client, Duration::from_secs(5))
+                .await;
+        }
+    });
+    let asked = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let message = impostor.read().await.unwrap().unwrap();
+            if let Payload::RequestAsk(_) = message.payload {
+                break;
+            }
+        }
+    })
+    .await;
+    assert!(asked.is_ok(), "the impostor was treated as a peer");
+    requests.abort();
+}
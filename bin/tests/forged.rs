@@ -0,0 +1,76 @@
+use mees::{
+    internals::{
+        Message, Payload, RequestRegister, RequestResponse, ResponseStatus, SchemaQuery, StreamItem,
+    },
+    transport::{Connection, Transport},
+    Requestable,
+};
+
+#[tokio::test]
+pub async fn forged_response() {
+    mees::requests! {
+        Add (i32, i32) -> i32
+    };
+
+    let broker = mees_bin::Embedded::start();
+    let mut handler = Connection::new(broker.transport().connect().await.unwrap());
+    handler
+        .write(&Message::new(Payload::RequestRegister(RequestRegister {
+            path: Add::path().to_string(),
+            weight: 1,
+            schema: None,
+            instance: None,
+        })))
+        .await
+        .unwrap();
+    broker.wait_registered(Add::path(), 1).await;
+    let mut forger = Connection::new(broker.transport().connect().await.unwrap());
+
+    let client = broker.client().await.unwrap();
+    let (res, ()) = tokio::join!(Add(1, 2).request(&client), async {
+        let Some(Message {
+            payload: Payload::RequestAsk(request),
+        }) = handler.read().await.unwrap()
+        else {
+            panic!("expected a request");
+        };
+        // Answers from a connection the request was not sent to are dropped
+        forger
+            .write(&Message::new(Payload::StreamItem(StreamItem {
+                id: request.id,
+                data: Vec::new(),
+            })))
+            .await
+            .unwrap();
+        forger
+            .write(&Message::new(Payload::RequestResponse(RequestResponse {
+                id: request.id,
+                status: ResponseStatus::NoHandler,
+                data: Vec::new(),
+            })))
+            .await
+            .unwrap();
+        // The broker answers a connection's frames in order, so the forged ones were
+        // handled once this query is answered
+        forger
+            .write(&Message::new(Payload::SchemaQuery(SchemaQuery {
+                id: 0,
+                name: None,
+            })))
+            .await
+            .unwrap();
+        while !matches!(
+            forger.read().await.unwrap().unwrap().payload,
+            Payload::SchemaList(_)
+        ) {}
+        handler
+            .write(&Message::new(Payload::RequestResponse(RequestResponse {
+                id: request.id,
+                status: ResponseStatus::Timeout,
+                data: Vec::new(),
+            })))
+            .await
+            .unwrap();
+    });
+    assert!(matches!(res, Err(mees::RequestError::Timeout)));
+}
@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use mees::{RequestError, Requestable};
+
+mees::requests! {
+    Echo (i32) -> i32
+    #[mees(idempotent)]
+    Lookup (i32) -> i32
+}
+
+#[tokio::test]
+pub async fn handler_lost() {
+    let broker = mees_bin::Embedded::start();
+
+    let mut stuck = mees::Responder::new();
+    stuck.register(Echo::handler(|echo| async move {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        echo.0
+    }));
+    let stuck = broker.responder(stuck).await;
+    let mut responder = mees::Responder::new();
+    responder.register(Echo::handler(|echo| async move { echo.0 }));
+    broker.responder(responder).await;
+
+    let client = broker.client().await.unwrap();
+
+    // The first request goes to the first responder, which goes away while handling it
+    let (lost, ()) = tokio::join!(Echo(1).request(&client), async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        stuck.abort();
+    });
+    assert!(matches!(lost, Err(RequestError::HandlerLost { .. })));
+
+    // Nothing is routed to the closed connection anymore
+    for i in 0..4 {
+        let echo = tokio::time::timeout(Duration::from_secs(5), Echo(i).request(&client)).await;
+        assert_eq!(echo.unwrap().unwrap(), i);
+    }
+}
+
+#[tokio::test]
+pub async fn idempotent_retry() {
+    let broker = mees_bin::Embedded::start();
+
+    let mut stuck = mees::Responder::new();
+    stuck.register(Lookup::handler(|lookup| async move {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        lookup.0
+    }));
+    let stuck = broker.responder(stuck).await;
+    let mut responder = mees::Responder::new();
+    responder.register(Lookup::handler(|lookup| async move { lookup.0 * 2 }));
+    broker.responder(responder).await;
+
+    let client = broker.client().await.unwrap();
+
+    // The request is sent to the other responder instead of failing
+    let (retried, ()) = tokio::join!(Lookup(21).request(&client), async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        stuck.abort();
+    });
+    assert_eq!(retried.unwrap(), 42);
+}
+
+#[tokio::test]
+pub async fn retry_budget() {
+    let broker = mees_bin::Embedded::with_config(mees_bin::Config {
+        retry_budget: 0,
+        ..mees_bin::Config::default()
+    })
+    .unwrap();
+
+    let mut stuck = mees::Responder::new();
+    stuck.register(Lookup::handler(|lookup| async move {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        lookup.0
+    }));
+    let stuck = broker.responder(stuck).await;
+    let mut responder = mees::Responder::new();
+    responder.register(Lookup::handler(|lookup| async move { lookup.0 }));
+    broker.responder(responder).await;
+
+    let client = broker.client().await.unwrap();
+    let (lost, ()) = tokio::join!(Lookup(1).request(&client), async {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        stuck.abort();
+    });
+    assert!(matches!(lost, Err(RequestError::HandlerLost { .. })));
+}
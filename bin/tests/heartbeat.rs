@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use mees::{
+    internals::{Message, Payload, RequestRegister},
+    transport::{memory, Connection, Transport},
+    ClientOptions, ConnectionState, Heartbeat, Requestable,
+};
+
+fn heartbeat() -> Heartbeat {
+    Heartbeat {
+        interval: Duration::from_millis(50),
+        max_missed: 2,
+    }
+}
+
+#[tokio::test]
+pub async fn broker_evicts_silent_handler() {
+    mees::requests! {
+        Add (i32, i32) -> i32
+    };
+
+    let broker = mees_bin::Embedded::with_config(mees_bin::Config {
+        heartbeat: heartbeat(),
+        ..mees_bin::Config::default()
+    })
+    .unwrap();
+
+    // A handler that registers and then never answers anything
+    let mut silent = Connection::new(broker.transport().connect().await.unwrap());
+    silent
+        .write(&Message::new(Payload::RequestRegister(RequestRegister {
+            path: Add::path().to_string(),
+            weight: 1,
+            schema: None,
+            instance: None,
+        })))
+        .await
+        .unwrap();
+    broker.wait_registered(Add::path(), 1).await;
+
+    let client = broker.client().await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let res = Add(1, 2)
+                .request_timeout(&client, Duration::from_millis(100))
+                .await;
+            if matches!(res, Err(mees::RequestError::NoHandler { .. })) {
+                break;
+            }
+        }
+    })
+    .await
+    .expect("the silent handler was not evicted");
+}
+
+#[tokio::test]
+pub async fn client_detects_silent_broker() {
+    let (transport, _listener) = memory();
+    let client = mees::Client::with_transport(
+        transport,
+        ClientOptions {
+            heartbeat: heartbeat(),
+            ..ClientOptions::default()
+        },
+    )
+    .await
+    .unwrap();
+
+    let mut state = client.watch_state();
+    tokio::time::timeout(
+        Duration::from_secs(1),
+        state.wait_for(|state| *state != ConnectionState::Connected),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+}
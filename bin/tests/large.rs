@@ -0,0 +1,100 @@
+use mees::{ClientOptions, FrameTooLarge, Requestable, ResponderOptions};
+
+#[tokio::test]
+pub async fn large() {
+    mees::requests! {
+        Report (usize) -> Vec<u8>
+        Upload (Vec<u8>) -> usize
+    };
+
+    let broker = mees_bin::Embedded::start();
+
+    let mut responder = mees::Responder::with_options(ResponderOptions {
+        max_frame_size: 2 * 1024 * 1024,
+        ..ResponderOptions::default()
+    });
+    responder.register(Report::handler(|report| async move { vec![7; report.0] }));
+    responder.register(Upload::handler(|upload| async move { upload.0.len() }));
+    broker.responder(responder).await;
+
+    let client = broker.client().await.unwrap();
+    let report = Report(1024 * 1024).request(&client).await.unwrap();
+    assert_eq!(report.len(), 1024 * 1024);
+    assert!(report.iter().all(|&b| b == 7));
+
+    // The responder can not send a response over its limit, but the caller learns why
+    match Report(3 * 1024 * 1024).request(&client).await {
+        Err(mees::RequestError::FrameTooLarge(FrameTooLarge { max, .. })) => {
+            assert_eq!(max, 2 * 1024 * 1024);
+        }
+        res => panic!("unexpected result: {:?}", res.map(|r| r.len())),
+    }
+    // The connection is still usable afterwards
+    assert_eq!(Upload(vec![0; 2048]).request(&client).await.unwrap(), 2048);
+
+    let small = broker
+        .client_with_options(ClientOptions {
+            max_frame_size: 1024,
+            ..ClientOptions::default()
+        })
+        .await
+        .unwrap();
+    assert!(matches!(
+        Upload(vec![0; 2048]).request(&small).await,
+        Err(mees::RequestError::FrameTooLarge(_))
+    ));
+    assert_eq!(Upload(vec![0; 512]).request(&small).await.unwrap(), 512);
+}
+
+#[tokio::test]
+pub async fn skipped_frames() {
+    mees::requests! {
+        Report (usize) -> Vec<u8>
+        Upload (Vec<u8>) -> usize
+    };
+
+    // The responder allows larger frames than the broker
+    let broker = mees_bin::Embedded::with_config(mees_bin::Config {
+        max_frame_size: 64 * 1024,
+        ..mees_bin::Config::default()
+    })
+    .unwrap();
+    let mut responder = mees::Responder::new();
+    responder.register(Report::handler(|report| async move { vec![7; report.0] }));
+    responder.register(Upload::handler(|upload| async move { upload.0.len() }));
+    broker.responder(responder).await;
+
+    let client = broker.client().await.unwrap();
+    match Report(128 * 1024).request(&client).await {
+        Err(mees::RequestError::FrameTooLarge(FrameTooLarge { max, .. })) => {
+            assert_eq!(max, 64 * 1024);
+        }
+        res => panic!("unexpected result: {:?}", res.map(|r| r.len())),
+    }
+    match Upload(vec![0; 128 * 1024]).request(&client).await {
+        Err(mees::RequestError::FrameTooLarge(FrameTooLarge { max, .. })) => {
+            assert_eq!(max, 64 * 1024);
+        }
+        res => panic!("unexpected result: {res:?}"),
+    }
+    // Neither the responder nor the client were disconnected
+    assert_eq!(Report(1024).request(&client).await.unwrap().len(), 1024);
+
+    // A client with a smaller maximum fails only the request whose response is too large
+    let small = broker
+        .client_with_options(ClientOptions {
+            max_frame_size: 1024,
+            ..ClientOptions::default()
+        })
+        .await
+        .unwrap();
+    let (large, fits) = tokio::join!(Report(2048).request(&small), Report(512).request(&small));
+    match large {
+        Err(mees::RequestError::FrameTooLarge(FrameTooLarge { max, .. })) => {
+            assert_eq!(max, 1024);
+        }
+        res => panic!("unexpected result: {:?}", res.map(|r| r.len())),
+    }
+    assert_eq!(fits.unwrap().len(), 512);
+    assert_eq!(small.state(), mees::ConnectionState::Connected);
+}
@@ -0,0 +1,18 @@
+use mees::Requestable;
+
+#[tokio::test]
+pub async fn no_handler() {
+    mees::requests! {
+        Add (i32, i32) -> i32
+    };
+
+    let broker = mees_bin::Embedded::start();
+    // Nothing to wait for without handlers
+    broker.wait_registered(Add::path(), 0).await;
+
+    let client = broker.client().await.unwrap();
+    match Add(1, 2).request(&client).await {
+        Err(mees::RequestError::NoHandler { path }) => assert_eq!(path, Add::path()),
+        res => panic!("unexpected result: {res:?}"),
+    }
+}
@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use mees::{
+    internals::{Message, Payload, RequestResponse, ResponseStatus},
+    transport::{memory, Connection, Listener},
+    Backoff, ClientOptions, ConnectionState, Requestable,
+};
+
+async fn read_message(connection: &mut Connection) -> Message {
+    connection.read().await.unwrap().unwrap()
+}
+
+#[tokio::test]
+pub async fn reconnect() {
+    mees::requests! {
+        Add (i32, i32) -> i32
+    };
+
+    let (transport, listener) = memory();
+    let client = mees::Client::with_transport(
+        transport,
+        ClientOptions {
+            backoff: Backoff {
+                initial: Duration::from_millis(10),
+                ..Backoff::default()
+            },
+            ..ClientOptions::default()
+        },
+    )
+    .await
+    .unwrap();
+    let mut state = client.watch_state();
+
+    // The first connection is dropped while a request is in flight
+    let mut connection = Connection::new(listener.accept().await.unwrap());
+    let (res, ()) = tokio::join!(Add(1, 2).request(&client), async move {
+        read_message(&mut connection).await;
+    });
+    assert!(matches!(res, Err(mees::RequestError::ConnectionLost)));
+
+    // The client reconnects and the next request is answered
+    let mut connection = Connection::new(listener.accept().await.unwrap());
+    state
+        .wait_for(|state| *state == ConnectionState::Connected)
+        .await
+        .unwrap();
+    let (res, _) = tokio::join!(Add(1, 2).request(&client), async move {
+        let Payload::RequestAsk(ask) = read_message(&mut connection).await.payload else {
+            panic!("expected a request");
+        };
+        connection
+            .write(&Message::new(Payload::RequestResponse(RequestResponse {
+                id: ask.id,
+                status: ResponseStatus::NoHandler,
+                data: Vec::new(),
+            })))
+            .await
+            .unwrap();
+        connection
+    });
+    assert!(matches!(res, Err(mees::RequestError::NoHandler { .. })));
+}
@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+use mees::{
+    internals::{Message, Payload},
+    transport::{memory, Connection, Listener},
+    Backoff, Requestable,
+};
+
+#[tokio::test]
+pub async fn responder_reconnect() {
+    mees::requests! {
+        Add (i32, i32) -> i32
+    };
+
+    let (transport, listener) = memory();
+    let mut responder = mees::Responder::new();
+    responder.register(Add::handler(|add| async move { add.0 + add.1 }));
+    tokio::spawn(async move {
+        responder
+            .run_forever_with_transport(
+                &transport,
+                Backoff {
+                    initial: Duration::from_millis(10),
+                    ..Backoff::default()
+                },
+            )
+            .await;
+    });
+
+    // The handlers are registered again on every new connection
+    for _ in 0..2 {
+        let mut connection = Connection::new(listener.accept().await.unwrap());
+        let Some(Message {
+            payload: Payload::RequestRegister(register),
+        }) = connection.read().await.unwrap()
+        else {
+            panic!("expected a registration");
+        };
+        assert_eq!(register.path, Add::path());
+    }
+}
@@ -0,0 +1,58 @@
+use std::collections::{HashMap, HashSet};
+
+use mees::{Requestable, ResponderOptions};
+
+mees::requests! {
+    Owner {
+        #[mees(route_key)]
+        user: u32,
+        attempt: u32,
+    } -> char
+}
+
+#[tokio::test]
+pub async fn route_key() {
+    let broker = mees_bin::Embedded::start();
+    for name in ['a', 'b', 'c'] {
+        let mut responder = mees::Responder::new();
+        responder.register(Owner::handler(move |_| async move { name }));
+        broker.responder(responder).await;
+    }
+
+    let client = broker.client().await.unwrap();
+    let mut owners = HashMap::new();
+    for attempt in 0..3 {
+        for user in 0..20 {
+            let owner = Owner { user, attempt }.request(&client).await.unwrap();
+            // Every request for a user goes to the same responder
+            assert_eq!(*owners.entry(user).or_insert(owner), owner);
+        }
+    }
+    assert!(owners.values().collect::<HashSet<_>>().len() > 1);
+}
+
+/// The responder that owns each user, with responders named after what they answer
+async fn owners(names: [char; 3]) -> HashMap<u32, char> {
+    let broker = mees_bin::Embedded::start();
+    for name in names {
+        let mut responder = mees::Responder::with_options(ResponderOptions {
+            instance: Some(name.to_string()),
+            ..ResponderOptions::default()
+        });
+        responder.register(Owner::handler(move |_| async move { name }));
+        broker.responder(responder).await;
+    }
+    let client = broker.client().await.unwrap();
+    let mut owners = HashMap::new();
+    for user in 0..20 {
+        let owner = Owner { user, attempt: 0 }.request(&client).await.unwrap();
+        owners.insert(user, owner);
+    }
+    owners
+}
+
+#[tokio::test]
+pub async fn route_key_instances() {
+    // Named responders keep their users when they connect again in another order
+    assert_eq!(owners(['a', 'b', 'c']).await, owners(['c', 'a', 'b']).await);
+}
@@ -0,0 +1,86 @@
+use mees::{Never, RequestError};
+
+/// The callers that were not deployed yet
+mod v1 {
+    use mees::{Never, RequestError, Requestable};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Serialize, Deserialize)]
+    pub struct User {
+        pub name: String,
+    }
+    mees::requests! {
+        GetUser (u32) -> User
+    }
+
+    pub async fn get(client: &mees::Client, id: u32) -> Result<User, RequestError<Never>> {
+        GetUser(id).request(client).await
+    }
+}
+
+/// The handlers that were, with a field added to the response
+mod v2 {
+    use mees::Requestable;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    pub struct User {
+        pub name: String,
+        pub email: String,
+    }
+    mees::requests! {
+        GetUser (u32) -> User
+    }
+
+    pub fn responder() -> mees::Responder {
+        let mut responder = mees::Responder::new();
+        responder.register(GetUser::handler(|GetUser(id)| async move {
+            User {
+                name: id.to_string(),
+                email: format!("{id}@example.com"),
+            }
+        }));
+        responder
+    }
+
+    pub fn path() -> &'static str {
+        GetUser::path()
+    }
+
+    pub fn schema() -> mees::Schema {
+        GetUser::schema()
+    }
+}
+
+#[tokio::test]
+pub async fn schema_mismatch() {
+    let broker = mees_bin::Embedded::start();
+    let client = broker.client().await.unwrap();
+
+    // Without handlers of any version there is nothing to compare to
+    assert!(matches!(
+        v1::get(&client, 1).await,
+        Err(RequestError::NoHandler { .. })
+    ));
+
+    broker.responder(v2::responder()).await;
+    let schemas = client.schemas(Some("GetUser")).await.unwrap();
+    assert_eq!(schemas.len(), 1);
+    assert_eq!(schemas[v2::path()], v2::schema());
+    assert!(client.schemas(Some("Other")).await.unwrap().is_empty());
+    assert_eq!(client.schemas(None).await.unwrap(), schemas);
+
+    match v1::get(&client, 1).await {
+        Err(RequestError::<Never>::SchemaMismatch(e)) => {
+            assert_eq!(e.remote, vec![v2::schema()]);
+            assert_eq!(
+                e.local.difference(&e.remote[0]).as_deref(),
+                Some("adds `response.email`")
+            );
+            assert!(e
+                .to_string()
+                .ends_with("the handlers' schema adds `response.email`"));
+        }
+        res => panic!("unexpected result: {res:?}"),
+    }
+}
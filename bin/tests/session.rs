@@ -0,0 +1,54 @@
+use futures::StreamExt;
+use mees::{BidiStreamable, ClientStreamable, RequestError};
+
+#[tokio::test]
+pub async fn session() {
+    mees::requests! {
+        Upload { name: String } <- stream Vec<u8> -> usize
+        Echo (String) <- stream String -> stream String
+        Missing (i32) <- stream i32 -> i32
+    };
+
+    let broker = mees_bin::Embedded::start();
+
+    let mut responder = mees::Responder::new();
+    responder.register(Upload::handler(|_, mut chunks| async move {
+        let mut size = 0;
+        while let Some(chunk) = chunks.recv().await {
+            size += chunk.len();
+        }
+        size
+    }));
+    responder.register(Echo::handler(|echo, inputs| {
+        inputs.map(move |input| format!("{}{input}", echo.0))
+    }));
+    broker.responder(responder).await;
+
+    let client = broker.client().await.unwrap();
+    let upload = Upload {
+        name: "file".to_string(),
+    }
+    .request(&client)
+    .await
+    .unwrap();
+    for _ in 0..10 {
+        upload.send(&vec![0; 100]).await.unwrap();
+    }
+    assert_eq!(upload.finish().await.unwrap(), 1000);
+
+    // Responses are received while inputs are still being sent
+    let mut echo = Echo("> ".to_string()).request(&client).await.unwrap();
+    for i in 0..10 {
+        echo.send(&i.to_string()).await.unwrap();
+        assert_eq!(echo.next().await.unwrap().unwrap(), format!("> {i}"));
+    }
+    echo.close().await.unwrap();
+    assert!(echo.next().await.is_none());
+
+    let missing = Missing(1).request(&client).await.unwrap();
+    missing.send(&1).await.unwrap();
+    assert!(matches!(
+        missing.finish().await,
+        Err(RequestError::NoHandler { .. })
+    ));
+}
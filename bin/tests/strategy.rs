@@ -0,0 +1,64 @@
+use std::{collections::HashMap, time::Duration};
+
+use mees::{Requestable, ResponderOptions};
+use mees_bin::{Config, Strategy};
+
+mees::requests! {
+    Which () -> char
+}
+
+async fn responder(broker: &mees_bin::Embedded, name: char, weight: u32, delay: Duration) {
+    let mut responder = mees::Responder::with_options(ResponderOptions {
+        weight,
+        ..ResponderOptions::default()
+    });
+    responder.register(Which::handler(move |_| async move {
+        tokio::time::sleep(delay).await;
+        name
+    }));
+    broker.responder(responder).await;
+}
+
+#[tokio::test]
+pub async fn weighted() {
+    let broker = mees_bin::Embedded::with_config(Config {
+        path_strategies: HashMap::from([("Which".to_string(), Strategy::Weighted)]),
+        ..Config::default()
+    })
+    .unwrap();
+    responder(&broker, 'a', 3, Duration::ZERO).await;
+    responder(&broker, 'b', 1, Duration::ZERO).await;
+
+    let client = broker.client().await.unwrap();
+    let mut counts = HashMap::new();
+    for _ in 0..8 {
+        *counts
+            .entry(Which().request(&client).await.unwrap())
+            .or_insert(0) += 1;
+    }
+    assert_eq!(counts, HashMap::from([('a', 6), ('b', 2)]));
+}
+
+#[tokio::test]
+pub async fn least_outstanding() {
+    let broker = mees_bin::Embedded::with_config(Config {
+        strategy: Strategy::LeastOutstanding,
+        ..Config::default()
+    })
+    .unwrap();
+    responder(&broker, 'a', 1, Duration::from_secs(60)).await;
+    responder(&broker, 'b', 1, Duration::ZERO).await;
+
+    let client = broker.client().await.unwrap();
+    // While the first handler is busy, everything goes to the idle one
+    tokio::select! {
+        _ = Which().request(&client) => panic!("the slow handler answered"),
+        () = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            for _ in 0..4 {
+                let which = tokio::time::timeout(Duration::from_secs(5), Which().request(&client));
+                assert_eq!(which.await.unwrap().unwrap(), 'b');
+            }
+        } => {}
+    }
+}
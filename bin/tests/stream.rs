@@ -0,0 +1,56 @@
+use mees::{RequestError, Streamable};
+use serde::{Deserialize, Serialize};
+
+#[tokio::test]
+pub async fn stream() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum CountdownError {
+        Liftoff,
+    }
+    mees::requests! {
+        ListUsers { count: usize } -> stream String
+        Countdown (i32) -> stream i32 | CountdownError
+        Missing (i32) -> stream i32
+    };
+
+    let broker = mees_bin::Embedded::start();
+
+    let mut responder = mees::Responder::new();
+    responder.register(ListUsers::handler(|list| {
+        futures::stream::iter((0..list.count).map(|i| format!("user {i}")))
+    }));
+    responder.register(Countdown::handler(|countdown| {
+        futures::stream::iter((0..=countdown.0).rev().map(|i| {
+            if i == 0 {
+                Err(CountdownError::Liftoff)
+            } else {
+                Ok(i)
+            }
+        }))
+    }));
+    broker.responder(responder).await;
+
+    let client = broker.client().await.unwrap();
+    let mut users = ListUsers { count: 100 }.request(&client).await.unwrap();
+    for i in 0..100 {
+        assert_eq!(users.next().await.unwrap().unwrap(), format!("user {i}"));
+    }
+    assert!(users.next().await.is_none());
+
+    // An error from the handler ends the stream
+    let mut countdown = Countdown(2).request(&client).await.unwrap();
+    assert_eq!(countdown.next().await.unwrap().unwrap(), 2);
+    assert_eq!(countdown.next().await.unwrap().unwrap(), 1);
+    match countdown.next().await {
+        Some(Err(RequestError::Handler(e))) => assert_eq!(e, CountdownError::Liftoff),
+        res => panic!("unexpected result: {res:?}"),
+    }
+    assert!(countdown.next().await.is_none());
+
+    let mut missing = Missing(1).request(&client).await.unwrap();
+    assert!(matches!(
+        missing.next().await,
+        Some(Err(RequestError::NoHandler { .. }))
+    ));
+    assert!(missing.next().await.is_none());
+}
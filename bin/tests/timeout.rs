@@ -0,0 +1,32 @@
+use std::time::Duration;
+
+use mees::Requestable;
+
+#[tokio::test]
+pub async fn timeout() {
+    mees::requests! {
+        Sleep (u64) -> u64
+    };
+
+    let broker = mees_bin::Embedded::start();
+
+    let mut responder = mees::Responder::new();
+    responder.register(Sleep::handler(|sleep| async move {
+        tokio::time::sleep(Duration::from_millis(sleep.0)).await;
+        sleep.0
+    }));
+    broker.responder(responder).await;
+
+    let client = broker.client().await.unwrap();
+    let res = Sleep(5000)
+        .request_timeout(&client, Duration::from_millis(200))
+        .await;
+    assert!(matches!(res, Err(mees::RequestError::Timeout)));
+
+    // The responder abandons the slow handler, so it is free for the next request
+    let res = Sleep(10)
+        .request_timeout(&client, Duration::from_millis(1000))
+        .await
+        .unwrap();
+    assert_eq!(res, 10);
+}
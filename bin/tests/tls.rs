@@ -0,0 +1,89 @@
+#![cfg(feature = "tls")]
+
+use std::time::Duration;
+
+use mees::{
+    tls::{TlsClientConfig, TlsListener, TlsServerConfig, TlsTransport},
+    transport::memory,
+    ClientOptions, Requestable,
+};
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+struct Fixtures {
+    ca: String,
+    cert: String,
+    key: String,
+}
+
+/// A self-signed certificate authority and a certificate for `localhost` signed by it
+fn fixtures() -> Fixtures {
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+
+    let key = KeyPair::generate().unwrap();
+    let cert = CertificateParams::new(vec!["localhost".to_string()])
+        .unwrap()
+        .signed_by(&key, &ca, &ca_key)
+        .unwrap();
+    Fixtures {
+        ca: ca.pem(),
+        cert: cert.pem(),
+        key: key.serialize_pem(),
+    }
+}
+
+#[tokio::test]
+pub async fn tls() {
+    mees::requests! {
+        Add (i32, i32) -> i32
+    };
+
+    let fixtures = fixtures();
+    let server =
+        TlsServerConfig::from_pem(fixtures.cert.as_bytes(), fixtures.key.as_bytes()).unwrap();
+    let (address, listener) = memory();
+    tokio::spawn(async move {
+        mees_bin::run_with_listener(
+            TlsListener::new(listener, server),
+            mees_bin::Config::default(),
+        )
+        .await
+        .unwrap();
+    });
+
+    let client_tls = TlsClientConfig::from_pem(fixtures.ca.as_bytes(), "localhost").unwrap();
+    let mut responder = mees::Responder::new();
+    responder.register(Add::handler(|add| async move { add.0 + add.1 }));
+    let transport = TlsTransport::new(address.clone(), client_tls.clone());
+    tokio::spawn(async move {
+        responder.run_with_transport(&transport).await.unwrap();
+    });
+
+    let client = mees::Client::with_transport(
+        TlsTransport::new(address.clone(), client_tls),
+        ClientOptions::default(),
+    )
+    .await
+    .unwrap();
+    // The broker's registry is not reachable from here, its schemas show the handler
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while client.schemas(Some("Add")).await.unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(Add(1, 2).request(&client).await.unwrap(), 3);
+
+    // A client pinned to another certificate authority refuses the broker
+    let other = self::fixtures();
+    let other = TlsClientConfig::from_pem(other.ca.as_bytes(), "localhost").unwrap();
+    assert!(mees::Client::with_transport(
+        TlsTransport::new(address, other),
+        ClientOptions::default(),
+    )
+    .await
+    .is_err());
+}
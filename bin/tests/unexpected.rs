@@ -0,0 +1,49 @@
+use mees::{
+    internals::{Control, Message, Payload, RequestResponse, ResponseStatus, SessionClose},
+    transport::{memory, Connection, Listener},
+    ClientOptions, ConnectionState, Requestable,
+};
+
+#[tokio::test]
+pub async fn unexpected_frames() {
+    mees::requests! {
+        Add (i32, i32) -> i32
+    };
+
+    // A broker that sends frames a client never expects before answering
+    let (transport, listener) = memory();
+    tokio::spawn(async move {
+        let mut connection = Connection::new(listener.accept().await.unwrap());
+        let Some(Message {
+            payload: Payload::RequestAsk(request),
+        }) = connection.read().await.unwrap()
+        else {
+            panic!("expected a request");
+        };
+        for payload in [
+            Payload::Control(Control::Peer),
+            Payload::SessionClose(SessionClose { id: request.id }),
+        ] {
+            connection.write(&Message::new(payload)).await.unwrap();
+        }
+        connection
+            .write(&Message::new(Payload::RequestResponse(RequestResponse {
+                id: request.id,
+                status: ResponseStatus::NoHandler,
+                data: Vec::new(),
+            })))
+            .await
+            .unwrap();
+        std::future::pending::<()>().await;
+    });
+
+    let client = mees::Client::with_transport(transport, ClientOptions::default())
+        .await
+        .unwrap();
+    // The response is still read after the unexpected frames
+    assert!(matches!(
+        Add(1, 2).request(&client).await,
+        Err(mees::RequestError::NoHandler { .. })
+    ));
+    assert_eq!(client.state(), ConnectionState::Connected);
+}
@@ -0,0 +1,68 @@
+#![cfg(unix)]
+
+use std::time::Duration;
+
+use mees::Requestable;
+
+#[tokio::test]
+pub async fn unix() {
+    mees::requests! {
+        Add (i32, i32) -> i32
+    };
+
+    let path = std::env::temp_dir().join(format!("mees-test-{}.sock", std::process::id()));
+    let address = format!("unix://{}", path.display());
+
+    // Bound before the broker runs, so the socket accepts connections right away
+    let listener = mees::transport::bind(&address.as_str().into())
+        .await
+        .unwrap();
+    tokio::spawn(mees_bin::run_with_listener(
+        listener,
+        mees_bin::Config::default(),
+    ));
+
+    let mut responder = mees::Responder::new();
+    responder.register(Add::handler(|add| async move { add.0 + add.1 }));
+    let responder_address = address.clone();
+    tokio::spawn(async move {
+        responder.run(responder_address).await.unwrap();
+    });
+
+    let client = mees::Client::new(address).await.unwrap();
+    // The broker's registry is not reachable from here, its schemas show the handler
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while client.schemas(Some("Add")).await.unwrap().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    let res = Add(1, 2).request(&client).await.unwrap();
+    assert_eq!(res, 3);
+
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+pub async fn unix_bind() {
+    let path = std::env::temp_dir().join(format!("mees-test-bind-{}.sock", std::process::id()));
+    let address = mees::Address::Unix(path.clone());
+
+    // Another kind of file is left alone
+    std::fs::write(&path, b"").unwrap();
+    assert!(mees::transport::bind(&address).await.is_err());
+    assert!(path.is_file());
+    std::fs::remove_file(&path).unwrap();
+
+    // So is the socket of a running broker
+    let listener = mees::transport::bind(&address).await.unwrap();
+    assert!(mees::transport::bind(&address).await.is_err());
+
+    // A socket left behind by a broker that stopped is replaced
+    drop(listener);
+    assert!(path.exists());
+    mees::transport::bind(&address).await.unwrap();
+
+    let _ = std::fs::remove_file(path);
+}
@@ -0,0 +1,77 @@
+use std::{fmt::Display, net::SocketAddr, path::PathBuf};
+
+/// Where the broker listens, parsed from `host:port`, `tcp://host:port` or `unix:///path`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl Address {
+    pub fn parse(address: &str) -> Self {
+        if let Some(path) = address.strip_prefix("unix://") {
+            Self::Unix(PathBuf::from(path))
+        } else {
+            Self::Tcp(
+                address
+                    .strip_prefix("tcp://")
+                    .unwrap_or(address)
+                    .to_string(),
+            )
+        }
+    }
+}
+
+impl From<&str> for Address {
+    fn from(address: &str) -> Self {
+        Self::parse(address)
+    }
+}
+
+impl From<String> for Address {
+    fn from(address: String) -> Self {
+        Self::parse(&address)
+    }
+}
+
+impl From<&String> for Address {
+    fn from(address: &String) -> Self {
+        Self::parse(address)
+    }
+}
+
+impl From<SocketAddr> for Address {
+    fn from(address: SocketAddr) -> Self {
+        Self::Tcp(address.to_string())
+    }
+}
+
+impl Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(address) => write!(f, "tcp://{address}"),
+            Self::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Address::parse("localhost:6454"),
+            Address::Tcp("localhost:6454".to_string())
+        );
+        assert_eq!(
+            Address::parse("tcp://localhost:6454"),
+            Address::Tcp("localhost:6454".to_string())
+        );
+        assert_eq!(
+            Address::parse("unix:///run/mees.sock"),
+            Address::Unix(PathBuf::from("/run/mees.sock"))
+        );
+    }
+}
@@ -0,0 +1,20 @@
+use crate::{
+    internals::{Control, Message, Payload},
+    transport::Connection,
+};
+
+/// Send the shared secret as the first frame and wait for the broker to accept it
+pub async fn authenticate(connection: &mut Connection, secret: &str) -> std::io::Result<()> {
+    connection
+        .write(&Message::new(Payload::Control(Control::AuthPass(
+            secret.to_string(),
+        ))))
+        .await?;
+    match connection.read().await?.map(|message| message.payload) {
+        Some(Payload::Control(Control::AuthOk)) => Ok(()),
+        _ => Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            "authentication rejected by the broker",
+        )),
+    }
+}
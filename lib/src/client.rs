@@ -1,102 +1,639 @@
 use std::{
     collections::HashMap,
     fmt::Display,
-    sync::{atomic::AtomicU32, Arc},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+use tokio::sync::{
+    mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    oneshot::{self, Receiver, Sender},
+    watch, Mutex, RwLock,
 };
 
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
-    net::{
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpStream, ToSocketAddrs,
+use crate::{
+    auth::authenticate,
+    internals::{
+        Control, EventPublish, EventSubscribe, EventUnsubscribe, ResponseStatus, SchemaQuery,
+        SessionClose, StreamItem,
     },
-    sync::{oneshot::Sender, Mutex, RwLock},
+    response_stream::StreamFrame,
+    transport::{
+        Connection, ConnectionReader, ConnectionWriter, FramePart, SkippedFrame, Transport,
+        DEFAULT_MAX_FRAME_SIZE,
+    },
+    Address, Backoff, BidiStream, BidiStreamable, ClientStream, ClientStreamable, ConnectionLost,
+    Eventable, FrameTooLarge, Heartbeat, Message, Payload, Position, RequestResponse, Requestable,
+    ResponseStream, Schema, SendError, Streamable, Subscription,
 };
 
-use crate::{internals::Control, Message, RequestResponse, Requestable};
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    /// The delay between reconnection attempts
+    pub backoff: Backoff,
+    /// Fail requests immediately while disconnected instead of waiting for a reconnect
+    pub fail_fast: bool,
+    /// Pings used to detect a broker that stopped responding
+    pub heartbeat: Heartbeat,
+    /// The shared secret sent to the broker on every connection
+    pub auth: Option<String>,
+    /// The largest frame sent to or accepted from the broker, in bytes
+    pub max_frame_size: usize,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        Self {
+            backoff: Backoff::default(),
+            fail_fast: false,
+            heartbeat: Heartbeat::default(),
+            auth: None,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+}
+
+/// Where the reply to a request is delivered
+enum Pending {
+    Response(Sender<RequestResponse>),
+    Stream(UnboundedSender<StreamFrame>),
+    Schemas(Sender<HashMap<String, Schema>>),
+}
+
+struct Shared {
+    write: Mutex<Option<ConnectionWriter>>,
+    request_pending: RwLock<HashMap<u32, Pending>>,
+    event_subscribers: RwLock<HashMap<String, Vec<UnboundedSender<EventPublish>>>>,
+    /// Where each path subscribed with [`Client::subscribe_from`] resumes after a reconnect
+    event_resume: RwLock<HashMap<String, Position>>,
+    state: watch::Sender<ConnectionState>,
+}
+
+/// Cancels a request when dropped before its reply was received
+pub(crate) struct CancelOnDrop {
+    id: u32,
+    cancel: UnboundedSender<u32>,
+}
 
-type RequestPending = Arc<RwLock<HashMap<u32, Sender<RequestResponse>>>>;
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        let _ = self.cancel.send(self.id);
+    }
+}
 
 pub struct Client {
-    write: Mutex<BufWriter<OwnedWriteHalf>>,
-    request_pending: RequestPending,
+    shared: Arc<Shared>,
     request_pending_counter: AtomicU32,
+    /// The ids of dropped requests, see [`CancelOnDrop`]
+    cancel: UnboundedSender<u32>,
+    options: ClientOptions,
+    _shutdown: Sender<()>,
 }
 
 impl Client {
     pub async fn new<A>(address: A) -> Result<Self, Box<dyn std::error::Error>>
     where
-        A: ToSocketAddrs,
+        A: Into<Address>,
     {
-        let (read, write) = TcpStream::connect(address).await?.into_split();
-        let request_pending = Arc::new(RwLock::new(HashMap::new()));
-        tokio::spawn(Self::run(read, request_pending.clone()));
+        Self::with_options(address, ClientOptions::default()).await
+    }
+
+    pub async fn connect_with_auth<A>(
+        address: A,
+        secret: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        A: Into<Address>,
+    {
+        Self::with_options(
+            address,
+            ClientOptions {
+                auth: Some(secret.to_string()),
+                ..ClientOptions::default()
+            },
+        )
+        .await
+    }
+
+    pub async fn with_options<A>(
+        address: A,
+        options: ClientOptions,
+    ) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        A: Into<Address>,
+    {
+        Self::with_transport(address.into(), options).await
+    }
+
+    /// Connect to the broker through any transport, such as
+    /// [`TlsTransport`](crate::tls::TlsTransport) when the `tls` feature is enabled
+    pub async fn with_transport<T>(
+        transport: T,
+        options: ClientOptions,
+    ) -> Result<Self, Box<dyn std::error::Error>>
+    where
+        T: Transport + 'static,
+    {
+        let (read, write) = Self::connect(&transport, &options).await?.into_split();
+        let shared = Arc::new(Shared {
+            write: Mutex::new(Some(write)),
+            request_pending: RwLock::new(HashMap::new()),
+            event_subscribers: RwLock::new(HashMap::new()),
+            event_resume: RwLock::new(HashMap::new()),
+            state: watch::channel(ConnectionState::Connected).0,
+        });
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let (cancel, cancelled) = unbounded_channel();
+        tokio::spawn(Self::run(
+            Box::new(transport),
+            read,
+            shared.clone(),
+            options.clone(),
+            shutdown_rx,
+        ));
+        tokio::spawn(Self::cancel_dropped(shared.clone(), cancelled));
         Ok(Self {
-            write: Mutex::new(BufWriter::new(write)),
-            request_pending,
+            shared,
             request_pending_counter: AtomicU32::new(0),
+            cancel,
+            options,
+            _shutdown: shutdown,
         })
     }
 
-    pub async fn request(&self, request: &impl Requestable) -> RequestResponse {
+    async fn connect(
+        transport: &dyn Transport,
+        options: &ClientOptions,
+    ) -> std::io::Result<Connection> {
+        let mut connection =
+            Connection::with_max_frame_size(transport.connect().await?, options.max_frame_size);
+        if let Some(secret) = &options.auth {
+            authenticate(&mut connection, secret).await?;
+        }
+        Ok(connection)
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        *self.shared.state.borrow()
+    }
+
+    /// Observe changes to the connection state
+    pub fn watch_state(&self) -> watch::Receiver<ConnectionState> {
+        self.shared.state.subscribe()
+    }
+
+    async fn connected(&self) -> Result<(), ConnectionLost> {
+        if self.options.fail_fast {
+            return if self.state() == ConnectionState::Connected {
+                Ok(())
+            } else {
+                Err(ConnectionLost)
+            };
+        }
+        self.watch_state()
+            .wait_for(|state| *state == ConnectionState::Connected)
+            .await
+            .map(|_| ())
+            .map_err(|_| ConnectionLost)
+    }
+
+    async fn send(&self, message: &Message) -> Result<(), SendError> {
+        self.connected().await?;
+        let mut write = self.shared.write.lock().await;
+        let write = write.as_mut().ok_or(SendError::ConnectionLost)?;
+        write.write(message).await.map_err(|e| {
+            FrameTooLarge::from_io(&e).map_or(SendError::ConnectionLost, SendError::FrameTooLarge)
+        })
+    }
+
+    /// Send a request and wait for its response, dropping the returned future
+    /// cancels the request
+    pub async fn request(&self, request: &impl Requestable) -> Result<RequestResponse, SendError> {
+        let (id, rx) = self.pending().await;
+        let _cancel = self.cancel_on_drop(id);
+        if let Err(e) = self.send(&request.to_message(id)).await {
+            self.shared.request_pending.write().await.remove(&id);
+            return Err(e);
+        }
+        rx.await.map_err(|_| SendError::ConnectionLost)
+    }
+
+    /// Send a request and wait at most `timeout` for its response, the request is
+    /// cancelled when the timeout passes or the returned future is dropped
+    pub async fn request_with_timeout(
+        &self,
+        request: &impl Requestable,
+        timeout: Duration,
+    ) -> Result<RequestResponse, SendError> {
+        let (id, rx) = self.pending().await;
+        let _cancel = self.cancel_on_drop(id);
+        let mut message = request.to_message(id);
+        if let Payload::RequestAsk(ask) = &mut message.payload {
+            ask.timeout = Some(timeout);
+        }
+        let response = tokio::time::timeout(timeout, async {
+            self.send(&message).await?;
+            rx.await.map_err(|_| SendError::ConnectionLost)
+        })
+        .await;
+        match response {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(e)) => {
+                self.shared.request_pending.write().await.remove(&id);
+                Err(e)
+            }
+            // Still pending, so dropping `_cancel` tells the broker to stop the handler
+            Err(_) => Ok(RequestResponse {
+                id,
+                status: ResponseStatus::Timeout,
+                data: Vec::new(),
+            }),
+        }
+    }
+
+    /// Send a request answered by a stream of responses
+    pub async fn request_stream<R>(&self, request: &R) -> Result<ResponseStream<R>, SendError>
+    where
+        R: Streamable,
+    {
+        let (_, rx, cancel) = self.open(|id| request.to_message(id)).await?;
+        Ok(ResponseStream::new(rx, R::path(), R::schema, cancel))
+    }
+
+    /// Open a session that sends a stream of inputs and gets a single response
+    pub async fn request_client_stream<R>(
+        &self,
+        request: &R,
+    ) -> Result<ClientStream<'_, R>, SendError>
+    where
+        R: ClientStreamable,
+    {
+        let (id, rx, cancel) = self.open(|id| request.to_message(id)).await?;
+        Ok(ClientStream::new(self, id, rx, R::path(), cancel))
+    }
+
+    /// Open a session that sends a stream of inputs and gets a stream of responses
+    pub async fn request_bidi_stream<R>(&self, request: &R) -> Result<BidiStream<'_, R>, SendError>
+    where
+        R: BidiStreamable,
+    {
+        let (id, rx, cancel) = self.open(|id| request.to_message(id)).await?;
+        Ok(BidiStream::new(self, id, rx, R::path(), cancel))
+    }
+
+    /// The schemas of the handlers registered with the broker by path, only those of
+    /// requests named `name` when it is given
+    pub async fn schemas(&self, name: Option<&str>) -> Result<HashMap<String, Schema>, SendError> {
+        let (tx, rx) = oneshot::channel();
+        let id = self.insert_pending(Pending::Schemas(tx)).await;
+        let query = SchemaQuery {
+            id,
+            name: name.map(str::to_string),
+        };
+        if let Err(e) = self.send(&Message::new(Payload::SchemaQuery(query))).await {
+            self.shared.request_pending.write().await.remove(&id);
+            return Err(e);
+        }
+        rx.await.map_err(|_| SendError::ConnectionLost)
+    }
+
+    async fn open(
+        &self,
+        message: impl FnOnce(u32) -> Message,
+    ) -> Result<(u32, UnboundedReceiver<StreamFrame>, CancelOnDrop), SendError> {
+        let (tx, rx) = unbounded_channel();
+        let id = self.insert_pending(Pending::Stream(tx)).await;
+        if let Err(e) = self.send(&message(id)).await {
+            self.shared.request_pending.write().await.remove(&id);
+            return Err(e);
+        }
+        Ok((id, rx, self.cancel_on_drop(id)))
+    }
+
+    fn cancel_on_drop(&self, id: u32) -> CancelOnDrop {
+        CancelOnDrop {
+            id,
+            cancel: self.cancel.clone(),
+        }
+    }
+
+    /// Tell the broker about requests dropped before their reply was received
+    async fn cancel_dropped(shared: Arc<Shared>, mut cancelled: UnboundedReceiver<u32>) {
+        while let Some(id) = cancelled.recv().await {
+            // Requests that were answered are no longer pending
+            if shared.request_pending.write().await.remove(&id).is_some() {
+                Self::control(&shared, Control::Cancel(id)).await;
+            }
+        }
+    }
+
+    /// Send one input to the handler of session `id`
+    pub(crate) async fn send_input<T>(&self, id: u32, input: &T) -> Result<(), SendError>
+    where
+        T: Serialize,
+    {
+        self.send(&Message::new(Payload::SessionSend(StreamItem {
+            id,
+            data: crate::encode(input),
+        })))
+        .await
+    }
+
+    /// Tell the handler of session `id` that no more inputs will be sent
+    pub(crate) async fn close_input(&self, id: u32) -> Result<(), SendError> {
+        self.send(&Message::new(Payload::SessionClose(SessionClose { id })))
+            .await
+    }
+
+    async fn pending(&self) -> (u32, Receiver<RequestResponse>) {
+        let (tx, rx) = oneshot::channel();
+        (self.insert_pending(Pending::Response(tx)).await, rx)
+    }
+
+    async fn insert_pending(&self, pending: Pending) -> u32 {
+        let mut request_pending = self.shared.request_pending.write().await;
         let id = loop {
-            let id = self
-                .request_pending_counter
-                .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-            if !self.request_pending.read().await.contains_key(&id) {
+            let id = self.request_pending_counter.fetch_add(1, Ordering::Relaxed);
+            if !request_pending.contains_key(&id) {
                 break id;
             }
         };
-        let (tx, rx) = tokio::sync::oneshot::channel();
-        self.request_pending.write().await.insert(id, tx);
-        let message = request.to_message(id);
-        let bytes = message.to_bytes();
-        let count = bytes.len();
+        request_pending.insert(id, pending);
+        id
+    }
+
+    pub async fn publish(&self, event: &impl Eventable) -> Result<(), SendError> {
+        self.send(&event.to_message()).await
+    }
+
+    /// Subscribe to events of type `E`, the subscription is restored after a reconnect
+    pub async fn subscribe<E>(&self) -> Subscription<E>
+    where
+        E: Eventable,
+    {
+        let (tx, rx) = unbounded_channel();
+        let first = {
+            let mut event_subscribers = self.shared.event_subscribers.write().await;
+            let senders = event_subscribers.entry(E::path().to_string()).or_default();
+            senders.push(tx);
+            senders.len() == 1
+        };
+        if first {
+            // If the connection is down, the subscription is sent once it is restored
+            let _ = self
+                .send(&Message::new(Payload::EventSubscribe(EventSubscribe {
+                    path: E::path().to_string(),
+                    from: None,
+                })))
+                .await;
+        }
+        Subscription::new(rx)
+    }
+
+    /// Subscribe to events of type `E`, replaying the broker's event log from `from`,
+    /// after a reconnect the subscription resumes after the last event received
+    ///
+    /// Every subscription to `E` on this client receives the replayed events.
+    /// Without an event log on the broker, only new events are received
+    pub async fn subscribe_from<E>(&self, from: Position) -> Subscription<E>
+    where
+        E: Eventable,
+    {
+        let (tx, rx) = unbounded_channel();
+        let path = E::path().to_string();
+        self.shared
+            .event_subscribers
+            .write()
+            .await
+            .entry(path.clone())
+            .or_default()
+            .push(tx);
+        self.shared
+            .event_resume
+            .write()
+            .await
+            .insert(path.clone(), from);
+        // If the connection is down, the subscription is sent once it is restored
+        let _ = self
+            .send(&Message::new(Payload::EventSubscribe(EventSubscribe {
+                path,
+                from: Some(from),
+            })))
+            .await;
+        Subscription::new(rx)
+    }
+
+    pub async fn unsubscribe<E>(&self) -> Result<(), ConnectionLost>
+    where
+        E: Eventable,
+    {
+        if self
+            .shared
+            .event_subscribers
+            .write()
+            .await
+            .remove(E::path())
+            .is_some()
         {
-            let mut write = self.write.lock().await;
-            write.write_u32(count as u32).await.unwrap();
-            write.write_all(&bytes).await.unwrap();
-            write.flush().await.unwrap();
+            self.shared.event_resume.write().await.remove(E::path());
+            self.send(&Message::new(Payload::EventUnsubscribe(EventUnsubscribe {
+                path: E::path().to_string(),
+            })))
+            .await
+            .map_err(|_| ConnectionLost)?;
         }
-        rx.await.unwrap()
+        Ok(())
     }
 
-    pub async fn run(read: OwnedReadHalf, request_pending: RequestPending) {
-        let mut read = BufReader::new(read);
-        let mut buffer = [0; 1024];
+    async fn run(
+        transport: Box<dyn Transport>,
+        read: ConnectionReader,
+        shared: Arc<Shared>,
+        options: ClientOptions,
+        mut shutdown: Receiver<()>,
+    ) {
+        let mut read = read;
         loop {
-            let n = read.read_u32().await.unwrap();
-            if n == 0 {
-                break;
+            let missed = AtomicU32::new(0);
+            tokio::select! {
+                _ = &mut shutdown => break,
+                () = Self::read(read, &shared, &missed) => {}
+                // A silent broker is treated the same as a closed connection
+                () = options.heartbeat.monitor(&missed, || Self::control(&shared, Control::Ping)) => {}
             }
-            let buf = &mut buffer[..n as usize];
-            read.read_exact(buf).await.unwrap();
-            let message = Message::from_bytes(buf);
+            // Fail everything that was waiting on the lost connection
+            *shared.write.lock().await = None;
+            shared.request_pending.write().await.clear();
+            shared.state.send_replace(ConnectionState::Disconnected);
+
+            let mut attempt = 0;
+            read = loop {
+                tokio::select! {
+                    _ = &mut shutdown => return,
+                    () = tokio::time::sleep(options.backoff.delay(attempt)) => {}
+                }
+                shared.state.send_replace(ConnectionState::Connecting);
+                if let Ok(connection) = Self::connect(&*transport, &options).await {
+                    let (read, write) = connection.into_split();
+                    if Self::restore(&shared, write).await.is_ok() {
+                        shared.state.send_replace(ConnectionState::Connected);
+                        break read;
+                    }
+                }
+                shared.state.send_replace(ConnectionState::Disconnected);
+                attempt = attempt.saturating_add(1);
+            };
+        }
+        shared.state.send_replace(ConnectionState::Disconnected);
+    }
+
+    /// Resubscribe to events on a new connection before making it available
+    async fn restore(shared: &Shared, mut write: ConnectionWriter) -> std::io::Result<()> {
+        let paths = shared
+            .event_subscribers
+            .read()
+            .await
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        let event_resume = shared.event_resume.read().await;
+        for path in paths {
+            let from = event_resume.get(&path).copied();
+            write
+                .write(&Message::new(Payload::EventSubscribe(EventSubscribe {
+                    path,
+                    from,
+                })))
+                .await?;
+        }
+        drop(event_resume);
+        *shared.write.lock().await = Some(write);
+        Ok(())
+    }
+
+    async fn control(shared: &Shared, control: Control) -> bool {
+        match shared.write.lock().await.as_mut() {
+            Some(write) => write
+                .write(&Message::new(Payload::Control(control)))
+                .await
+                .is_ok(),
+            None => false,
+        }
+    }
+
+    async fn read(mut read: ConnectionReader, shared: &Shared, missed: &AtomicU32) {
+        loop {
+            let message = match read.read().await {
+                Ok(Some(message)) => message,
+                Err(e) => match SkippedFrame::from_io(&e) {
+                    Some(skipped) => {
+                        missed.store(0, Ordering::Relaxed);
+                        Self::skipped(shared, skipped).await;
+                        continue;
+                    }
+                    None => break,
+                },
+                Ok(None) => break,
+            };
+            missed.store(0, Ordering::Relaxed);
             match message.payload {
-                crate::Payload::Control(_) => todo!(),
-                crate::Payload::RequestRegister(_) => todo!(),
-                crate::Payload::RequestAsk(_) => todo!(),
-                crate::Payload::RequestResponse(response) => {
-                    let mut request_pending = request_pending.write().await;
-                    if let Some(tx) = request_pending.remove(&response.id) {
-                        tx.send(response).unwrap();
-                    } else {
-                        todo!()
+                crate::Payload::Control(Control::Ping) => {
+                    Self::control(shared, Control::Pong).await;
+                }
+                crate::Payload::Control(Control::Pong | Control::AuthOk) => {}
+                crate::Payload::Control(Control::Disconnect) => break,
+                crate::Payload::RequestResponse(response) | crate::Payload::StreamEnd(response) => {
+                    let mut request_pending = shared.request_pending.write().await;
+                    // The caller may have already given up on the request
+                    match request_pending.remove(&response.id) {
+                        Some(Pending::Response(tx)) => {
+                            let _ = tx.send(response);
+                        }
+                        Some(Pending::Stream(tx)) => {
+                            let _ = tx.send(StreamFrame::End(response));
+                        }
+                        Some(Pending::Schemas(_)) | None => {}
+                    }
+                }
+                crate::Payload::SchemaList(list) => {
+                    let mut request_pending = shared.request_pending.write().await;
+                    if let Some(Pending::Schemas(tx)) = request_pending.remove(&list.id) {
+                        let _ = tx.send(list.schemas);
+                    }
+                }
+                crate::Payload::StreamItem(item) => {
+                    let request_pending = shared.request_pending.read().await;
+                    if let Some(Pending::Stream(tx)) = request_pending.get(&item.id) {
+                        let _ = tx.send(StreamFrame::Item(item.data));
+                    }
+                }
+                // Only sent to a broker or a responder, a client has nothing to do with them
+                crate::Payload::Control(_)
+                | crate::Payload::RequestRegister(_)
+                | crate::Payload::RequestUnregister(_)
+                | crate::Payload::RequestAsk(_)
+                | crate::Payload::EventSubscribe(_)
+                | crate::Payload::EventUnsubscribe(_)
+                | crate::Payload::SessionSend(_)
+                | crate::Payload::SessionClose(_)
+                | crate::Payload::SchemaQuery(_) => {}
+                crate::Payload::EventPublish(event) => {
+                    if let Some(offset) = event.offset {
+                        if let Some(from) = shared.event_resume.write().await.get_mut(&event.path) {
+                            *from = Position::Offset(offset + 1);
+                        }
+                    }
+                    let mut event_subscribers = shared.event_subscribers.write().await;
+                    if let Some(senders) = event_subscribers.get_mut(&event.path) {
+                        senders.retain(|tx| tx.send(event.clone()).is_ok());
                     }
                 }
             }
         }
     }
 
+    /// Fail the request whose response was too large to be read, cancelling it in case
+    /// more of a streamed response follows
+    async fn skipped(shared: &Shared, skipped: SkippedFrame) {
+        let FramePart::Response(id) = skipped.part else {
+            return;
+        };
+        let response = RequestResponse {
+            id,
+            status: ResponseStatus::FrameTooLarge(skipped.error),
+            data: Vec::new(),
+        };
+        let pending = shared.request_pending.write().await.remove(&id);
+        match pending {
+            Some(Pending::Response(tx)) => {
+                let _ = tx.send(response);
+            }
+            Some(Pending::Stream(tx)) => {
+                let _ = tx.send(StreamFrame::End(response));
+                Self::control(shared, Control::Cancel(id)).await;
+            }
+            Some(Pending::Schemas(_)) | None => {}
+        }
+    }
+
     pub async fn disconnect(self) {
-        let mut write = self.write.lock().await;
         let message = Message {
             payload: crate::Payload::Control(Control::Disconnect),
         };
-        let bytes = message.to_bytes();
-        write.write_u32(bytes.len() as u32).await.unwrap();
-        write.write_all(&bytes).await.unwrap();
-        write.flush().await.unwrap();
+        if let Some(write) = self.shared.write.lock().await.as_mut() {
+            let _ = write.write(&message).await;
+        }
     }
 }
 
@@ -0,0 +1,175 @@
+use std::fmt::{Debug, Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Schema;
+
+/// The error returned when making a request
+#[derive(Debug)]
+pub enum RequestError<E> {
+    /// The handler returned an error
+    Handler(E),
+    /// The handler could not decode the request
+    BadRequest(String),
+    /// The response could not be decoded
+    Decode(rmp_serde::decode::Error),
+    /// No handler is registered for the request
+    NoHandler { path: String },
+    /// The handler went away before responding, and the request was not retried
+    HandlerLost { path: String },
+    /// No handler is registered for the request, but handlers of another version of it are
+    SchemaMismatch(SchemaMismatch),
+    /// The request's deadline passed before a response was received
+    Timeout,
+    /// The request or its response was larger than a connection allows
+    FrameTooLarge(FrameTooLarge),
+    /// The connection to the broker was lost before a response was received
+    ConnectionLost,
+}
+
+impl<E> Display for RequestError<E>
+where
+    E: Display,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Handler(e) => write!(f, "handler error: {e}"),
+            Self::BadRequest(e) => write!(f, "handler could not decode the request: {e}"),
+            Self::Decode(e) => write!(f, "could not decode the response: {e}"),
+            Self::NoHandler { path } => write!(f, "no handler registered for {path}"),
+            Self::HandlerLost { path } => write!(f, "the handler for {path} was lost"),
+            Self::SchemaMismatch(e) => write!(f, "{e}"),
+            Self::Timeout => write!(f, "request timed out"),
+            Self::FrameTooLarge(e) => write!(f, "{e}"),
+            Self::ConnectionLost => write!(f, "{ConnectionLost}"),
+        }
+    }
+}
+
+impl<E> std::error::Error for RequestError<E> where E: Debug + Display {}
+
+impl<E> From<ConnectionLost> for RequestError<E> {
+    fn from(_: ConnectionLost) -> Self {
+        Self::ConnectionLost
+    }
+}
+
+impl<E> From<SendError> for RequestError<E> {
+    fn from(e: SendError) -> Self {
+        match e {
+            SendError::ConnectionLost => Self::ConnectionLost,
+            SendError::FrameTooLarge(e) => Self::FrameTooLarge(e),
+        }
+    }
+}
+
+/// The handlers registered under the name of a request expect another schema than
+/// the caller's, such as during a rolling deploy that added or removed a field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaMismatch {
+    pub path: String,
+    /// The caller's schema of the request
+    pub local: Schema,
+    /// The schemas of the registered handlers
+    pub remote: Vec<Schema>,
+}
+
+impl Display for SchemaMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "no handler registered for {} shares its schema",
+            self.path
+        )?;
+        if let Some(difference) = self
+            .remote
+            .first()
+            .and_then(|remote| self.local.difference(remote))
+        {
+            write!(f, ", the handlers' schema {difference}")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SchemaMismatch {}
+
+/// The connection to the broker is not available
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionLost;
+
+impl Display for ConnectionLost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "connection to the broker lost")
+    }
+}
+
+impl std::error::Error for ConnectionLost {}
+
+/// A frame was larger than the maximum frame size of the connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FrameTooLarge {
+    pub size: usize,
+    pub max: usize,
+}
+
+impl FrameTooLarge {
+    /// Find a `FrameTooLarge` wrapped in an IO error by a connection
+    pub(crate) fn from_io(e: &std::io::Error) -> Option<Self> {
+        e.get_ref()?.downcast_ref().copied()
+    }
+}
+
+impl Display for FrameTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "frame of {} bytes exceeds the maximum of {} bytes",
+            self.size, self.max
+        )
+    }
+}
+
+impl std::error::Error for FrameTooLarge {}
+
+impl From<FrameTooLarge> for std::io::Error {
+    fn from(e: FrameTooLarge) -> Self {
+        Self::new(std::io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// The error returned when a message could not be sent to the broker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// The connection to the broker is not available
+    ConnectionLost,
+    /// The message was larger than the connection allows, nothing was sent
+    FrameTooLarge(FrameTooLarge),
+}
+
+impl Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ConnectionLost => write!(f, "{ConnectionLost}"),
+            Self::FrameTooLarge(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+impl From<ConnectionLost> for SendError {
+    fn from(_: ConnectionLost) -> Self {
+        Self::ConnectionLost
+    }
+}
+
+/// The error type of requests that can not fail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Never {}
+
+impl Display for Never {
+    fn fmt(&self, _: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {}
+    }
+}
@@ -0,0 +1,67 @@
+use std::{
+    future::Future,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+/// Periodic pings used to detect a peer that stopped responding
+#[derive(Debug, Clone)]
+pub struct Heartbeat {
+    /// The delay between pings
+    pub interval: Duration,
+    /// The number of pings without any traffic from the peer before it is considered dead
+    pub max_missed: u32,
+}
+
+impl Heartbeat {
+    /// Send a ping every interval until the peer has missed too many or a ping can not be sent
+    ///
+    /// `missed` must be reset to zero whenever anything is received from the peer. A ping is
+    /// counted as missed before it is sent, and one still not sent after an interval is given
+    /// up on, so a peer whose writes are stuck is still detected
+    pub async fn monitor<F, Fut>(&self, missed: &AtomicU32, mut ping: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        loop {
+            tokio::time::sleep(self.interval).await;
+            if missed.fetch_add(1, Ordering::Relaxed) >= self.max_missed {
+                return;
+            }
+            if let Ok(false) = tokio::time::timeout(self.interval, ping()).await {
+                return;
+            }
+        }
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            max_missed: 3,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn stuck_ping() {
+        let heartbeat = Heartbeat {
+            interval: Duration::from_millis(10),
+            max_missed: 2,
+        };
+        // A ping that never gets written still counts as missed
+        let missed = AtomicU32::new(0);
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            heartbeat.monitor(&missed, std::future::pending),
+        )
+        .await
+        .unwrap();
+    }
+}
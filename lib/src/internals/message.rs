@@ -1,9 +1,28 @@
+use std::{collections::HashMap, time::Duration};
+
 use rmp_serde::Deserializer;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize)]
+use crate::{FrameTooLarge, Position, Schema};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RequestRegister {
     pub path: String,
+    /// The share of requests this handler gets when the broker balances by weight
+    pub weight: u32,
+    /// The schema of the request the handler answers, kept by the broker for callers
+    /// whose own schema differs
+    pub schema: Option<Schema>,
+    /// Names the responder across its connections, requests with a routing key keep
+    /// going to it when it reconnects
+    #[serde(default)]
+    pub instance: Option<String>,
+}
+
+/// The handler of a path is gone, sent by a broker to its peers
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RequestUnregister {
+    pub path: String,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -11,31 +30,129 @@ pub struct RequestAsk {
     pub id: u32,
     pub path: String,
     pub data: Vec<u8>,
+    /// How long the caller is willing to wait for a response
+    pub timeout: Option<Duration>,
+    /// The caller expects `StreamItem`s ended by a `StreamEnd` instead of a `RequestResponse`
+    pub stream: bool,
+    /// The caller sends `SessionSend`s to the same handler until it sends `SessionClose`
+    pub session: bool,
+    /// Requests with the same key are sent to the same handler while it stays registered
+    pub route_key: Option<Vec<u8>>,
+    /// The request may be sent to another handler if the one handling it is lost
+    pub idempotent: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ResponseStatus {
+    /// `data` holds the handler's response
+    Ok,
+    /// `data` holds the handler's error
+    Error,
+    /// The handler could not decode the request
+    BadRequest(String),
+    /// No handler is registered for the path
+    NoHandler,
+    /// The handler's connection closed before it responded
+    HandlerLost,
+    /// The request's deadline passed before a response was received
+    Timeout,
+    /// The response was larger than the responder's connection allows
+    FrameTooLarge(FrameTooLarge),
+    /// No handler is registered for the path, but handlers of a request with the same
+    /// name are, these are their schemas
+    SchemaMismatch(Vec<Schema>),
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct RequestResponse {
     pub id: u32,
+    pub status: ResponseStatus,
+    pub data: Vec<u8>,
+}
+
+/// One item of a streamed response
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct StreamItem {
+    pub id: u32,
+    pub data: Vec<u8>,
+}
+
+/// The caller has finished sending inputs to a session
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SessionClose {
+    pub id: u32,
+}
+
+/// Ask the broker for the schemas of the registered handlers
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SchemaQuery {
+    pub id: u32,
+    /// Only the handlers of requests with this name, all of them when `None`
+    pub name: Option<String>,
+}
+
+/// The answer to a [`SchemaQuery`]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SchemaList {
+    pub id: u32,
+    /// The schemas by path
+    pub schemas: HashMap<String, Schema>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EventSubscribe {
+    pub path: String,
+    /// Replay the broker's event log from here before following new events
+    pub from: Option<Position>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EventUnsubscribe {
+    pub path: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct EventPublish {
+    pub path: String,
     pub data: Vec<u8>,
+    /// The position of the event in the broker's event log, set by the broker
+    pub offset: Option<u64>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum Control {
     Ping,
     Pong,
     AuthPass(String),
+    AuthOk,
     Disconnect,
+    /// The caller gave up on the request with this id
+    Cancel(u32),
+    /// Sent first by a broker on a connection to a peer broker
+    Peer,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum Payload {
     Control(Control),
     RequestRegister(RequestRegister),
+    RequestUnregister(RequestUnregister),
     RequestAsk(RequestAsk),
     RequestResponse(RequestResponse),
+    EventSubscribe(EventSubscribe),
+    EventUnsubscribe(EventUnsubscribe),
+    EventPublish(EventPublish),
+    StreamItem(StreamItem),
+    /// Ends a streamed response, `data` holds the handler's error if there was one
+    StreamEnd(RequestResponse),
+    /// One input from the caller of a session, routed to the handler pinned for it
+    SessionSend(StreamItem),
+    SessionClose(SessionClose),
+    SchemaQuery(SchemaQuery),
+    SchemaList(SchemaList),
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Message {
     pub payload: Payload,
 }
@@ -45,8 +162,8 @@ impl Message {
         Self { payload }
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Self {
-        Deserialize::deserialize(&mut Deserializer::new(bytes)).unwrap()
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, rmp_serde::decode::Error> {
+        Deserialize::deserialize(&mut Deserializer::new(bytes))
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
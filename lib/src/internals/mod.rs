@@ -1,2 +1,28 @@
 mod message;
 pub use message::*;
+
+mod stream;
+pub use stream::*;
+
+pub use crate::auth::authenticate;
+
+/// The path of a request or event named `name`, for the code generated by
+/// `requests!` and `events!`
+pub fn path(name: &str, schema: &crate::Schema) -> String {
+    format!("{name}-{:016x}", schema.fingerprint())
+}
+
+/// The 64 bit FNV-1a hash of `bytes`, stable across releases and platforms
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// The routing key of a request, for the code generated for a `#[mees(route_key)]` field
+pub fn route_key<T>(key: &T) -> Vec<u8>
+where
+    T: serde::Serialize + ?Sized,
+{
+    crate::encode(key)
+}
@@ -1,68 +1,440 @@
-use std::{future::Future, io::Cursor};
+use std::{future::Future, io::Cursor, time::Duration};
 
-use internals::{Message, Payload, RequestAsk, RequestResponse};
+use futures_core::Stream;
+use internals::{
+    EventPublish, Message, Payload, RequestAsk, RequestResponse, ResponseStatus, StreamItem,
+};
 use rmp_serde::{Deserializer, Serializer};
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
 
 pub use async_trait;
-pub use mees_proc::requests;
+pub use mees_proc::{events, requests};
 pub use serde;
 
+mod address;
+pub use address::Address;
+
+mod backoff;
+pub use backoff::Backoff;
+
 mod client;
-pub use client::Client;
+pub use client::{Client, ClientOptions, ConnectionState};
+
+mod error;
+pub use error::{ConnectionLost, FrameTooLarge, Never, RequestError, SchemaMismatch, SendError};
+
+mod heartbeat;
+pub use heartbeat::Heartbeat;
 
 pub mod internals;
 
+mod auth;
+
 mod responder;
-pub use responder::Responder;
+pub use responder::{Responder, ResponderOptions};
 
-#[async_trait::async_trait]
-pub trait Requestable: Sized + DeserializeOwned + Serialize {
+mod schema;
+pub use schema::Schema;
+
+mod response_stream;
+pub use response_stream::ResponseStream;
+
+mod session;
+pub use session::{BidiStream, ClientStream, Inputs};
+
+mod subscription;
+pub use subscription::{Position, Subscription};
+
+#[cfg(feature = "tls")]
+pub mod tls;
+
+pub mod transport;
+
+/// The reply of a request, shared by every kind of request declared in `requests!`
+pub trait Responds {
+    /// The response, or each item of a streamed response
     type Response: Serialize + DeserializeOwned;
+    type Error: Serialize + DeserializeOwned;
+    /// The key of a `#[mees(route_key)]` field, requests with the same key
+    /// are handled by the same responder
+    fn route_key(&self) -> Option<Vec<u8>> {
+        None
+    }
+    /// Set by `#[mees(idempotent)]`, the broker may send the request to another
+    /// responder when the one handling it is lost
+    const IDEMPOTENT: bool = false;
+}
+
+#[async_trait::async_trait]
+pub trait Requestable: Responds + Sized + DeserializeOwned + Serialize {
     fn path() -> &'static str;
-    async fn handle_local(&self, responder: &Responder) -> Result<Self::Response, String> {
-        let response = responder.handle(self.to_message(0)).await;
-        Ok(Deserialize::deserialize(&mut Deserializer::new(Cursor::new(response.data))).unwrap())
+    /// The schema fingerprinted in [`Requestable::path`]
+    fn schema() -> Schema {
+        schema::request::<Self>(None, false)
+    }
+    async fn handle_local(
+        &self,
+        responder: &Responder,
+    ) -> Result<Self::Response, RequestError<Self::Error>> {
+        Self::from_response(
+            responder
+                .handle(request_ask(self, 0, Self::path(), false, false))
+                .await,
+        )
+    }
+    async fn request(&self, client: &Client) -> Result<Self::Response, RequestError<Self::Error>> {
+        Self::from_response(client.request(self).await?)
+    }
+    async fn request_timeout(
+        &self,
+        client: &Client,
+        timeout: Duration,
+    ) -> Result<Self::Response, RequestError<Self::Error>> {
+        Self::from_response(client.request_with_timeout(self, timeout).await?)
     }
-    async fn request(&self, client: &Client) -> Result<Self::Response, String> {
-        let response = client.request(self).await;
-        Ok(Deserialize::deserialize(&mut Deserializer::new(Cursor::new(response.data))).unwrap())
+    fn from_request(request: RequestAsk) -> Result<Self, rmp_serde::decode::Error> {
+        decode(&request.data)
     }
-    fn from_request(request: RequestAsk) -> Self {
-        Deserialize::deserialize(&mut Deserializer::new(Cursor::new(request.data))).unwrap()
+    fn from_response(
+        response: RequestResponse,
+    ) -> Result<Self::Response, RequestError<Self::Error>> {
+        decode(&response_data(response, Self::path(), Self::schema)?).map_err(RequestError::Decode)
     }
     fn to_message(&self, id: u32) -> Message {
-        Message {
-            payload: Payload::RequestAsk(RequestAsk {
-                id,
-                data: {
-                    let mut buf = Vec::new();
-                    self.serialize(&mut Serializer::new(&mut buf)).unwrap();
-                    buf
-                },
-                path: Self::path().to_string(),
+        ask(self, id, Self::path(), false, false)
+    }
+    fn handler<F, Fut, O>(handler: F) -> responder::Handler<Self>
+    where
+        Self: Send,
+        F: Fn(Self) -> Fut + Copy + Send + Sync + 'static,
+        Fut: Future<Output = O> + Send + Sync + 'static,
+        O: Reply<Self>,
+    {
+        responder::Handler {
+            path: Self::path(),
+            schema: Self::schema(),
+            handler: Box::new(move |request, _| {
+                Box::pin(async move {
+                    let id = request.id;
+                    match Self::from_request(request) {
+                        Ok(request) => reply(id, handler(request).await),
+                        Err(e) => bad_request(id, &e),
+                    }
+                })
             }),
+            phantom: std::marker::PhantomData,
         }
     }
-    fn handler<F, Fut>(handler: F) -> responder::Handler<Self>
+}
+
+/// A request answered with a stream of responses, declared with `-> stream` in `requests!`
+#[async_trait::async_trait]
+pub trait Streamable: Responds + Sized + DeserializeOwned + Serialize {
+    fn path() -> &'static str;
+    /// The schema fingerprinted in [`Streamable::path`]
+    fn schema() -> Schema {
+        schema::request::<Self>(None, true)
+    }
+    async fn request(
+        &self,
+        client: &Client,
+    ) -> Result<ResponseStream<Self>, RequestError<Self::Error>> {
+        Ok(client.request_stream(self).await?)
+    }
+    fn to_message(&self, id: u32) -> Message {
+        ask(self, id, Self::path(), true, false)
+    }
+    /// The stream returned by `handler` is sent to the caller item by item,
+    /// an error item ends the stream
+    fn handler<F, S, O>(handler: F) -> responder::Handler<Self>
     where
         Self: Send,
-        F: Fn(Self) -> Fut + Copy + Send + Sync + 'static,
-        Fut: Future<Output = Self::Response> + Send + Sync + 'static,
+        F: Fn(Self) -> S + Copy + Send + Sync + 'static,
+        S: Stream<Item = O> + Send + 'static,
+        O: Reply<Self>,
+    {
+        responder::Handler {
+            path: Self::path(),
+            schema: Self::schema(),
+            handler: Box::new(move |request, exchange| {
+                Box::pin(async move {
+                    let id = request.id;
+                    match decode(&request.data) {
+                        Ok(request) => reply_stream(id, handler(request), &exchange.items).await,
+                        Err(e) => bad_request(id, &e),
+                    }
+                })
+            }),
+            phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+/// A request that sends a stream of inputs to the handler pinned for it and gets a
+/// single response, declared with `<- stream Input` in `requests!`
+#[async_trait::async_trait]
+pub trait ClientStreamable: Responds + Sized + DeserializeOwned + Serialize {
+    type Input: Serialize + DeserializeOwned + Send;
+    fn path() -> &'static str;
+    /// The schema fingerprinted in [`ClientStreamable::path`]
+    fn schema() -> Schema {
+        schema::request::<Self>(Some(Schema::of::<Self::Input>()), false)
+    }
+    async fn request<'a>(
+        &self,
+        client: &'a Client,
+    ) -> Result<ClientStream<'a, Self>, RequestError<Self::Error>> {
+        Ok(client.request_client_stream(self).await?)
+    }
+    fn to_message(&self, id: u32) -> Message {
+        ask(self, id, Self::path(), false, true)
+    }
+    /// `handler` receives the inputs until the caller finishes sending
+    fn handler<F, Fut, O>(handler: F) -> responder::Handler<Self>
+    where
+        Self: Send,
+        F: Fn(Self, Inputs<Self::Input>) -> Fut + Copy + Send + Sync + 'static,
+        Fut: Future<Output = O> + Send + 'static,
+        O: Reply<Self>,
     {
         responder::Handler {
-            handler: Box::new(move |request| {
+            path: Self::path(),
+            schema: Self::schema(),
+            handler: Box::new(move |request, exchange| {
                 Box::pin(async move {
                     let id = request.id;
-                    let mut buf = Vec::new();
-                    handler(Self::from_request(request))
-                        .await
-                        .serialize(&mut Serializer::new(&mut buf))
-                        .unwrap();
-                    RequestResponse { id, data: buf }
+                    match decode(&request.data) {
+                        Ok(request) => {
+                            reply(id, handler(request, Inputs::new(exchange.inputs)).await)
+                        }
+                        Err(e) => bad_request(id, &e),
+                    }
                 })
             }),
             phantom: std::marker::PhantomData,
         }
     }
 }
+
+/// A request that sends a stream of inputs to the handler pinned for it and gets a
+/// stream of responses, declared with `<- stream Input -> stream Response` in `requests!`
+#[async_trait::async_trait]
+pub trait BidiStreamable: Responds + Sized + DeserializeOwned + Serialize {
+    type Input: Serialize + DeserializeOwned + Send;
+    fn path() -> &'static str;
+    /// The schema fingerprinted in [`BidiStreamable::path`]
+    fn schema() -> Schema {
+        schema::request::<Self>(Some(Schema::of::<Self::Input>()), true)
+    }
+    async fn request<'a>(
+        &self,
+        client: &'a Client,
+    ) -> Result<BidiStream<'a, Self>, RequestError<Self::Error>> {
+        Ok(client.request_bidi_stream(self).await?)
+    }
+    fn to_message(&self, id: u32) -> Message {
+        ask(self, id, Self::path(), true, true)
+    }
+    /// `handler` receives the inputs until the caller closes them, the stream it
+    /// returns is sent to the caller item by item, an error item ends the session
+    fn handler<F, S, O>(handler: F) -> responder::Handler<Self>
+    where
+        Self: Send,
+        F: Fn(Self, Inputs<Self::Input>) -> S + Copy + Send + Sync + 'static,
+        S: Stream<Item = O> + Send + 'static,
+        O: Reply<Self>,
+    {
+        responder::Handler {
+            path: Self::path(),
+            schema: Self::schema(),
+            handler: Box::new(move |request, exchange| {
+                Box::pin(async move {
+                    let id = request.id;
+                    match decode(&request.data) {
+                        Ok(request) => {
+                            let stream = handler(request, Inputs::new(exchange.inputs));
+                            reply_stream(id, stream, &exchange.items).await
+                        }
+                        Err(e) => bad_request(id, &e),
+                    }
+                })
+            }),
+            phantom: std::marker::PhantomData,
+        }
+    }
+}
+
+/// The value returned by a handler of `R`, either the response or a `Result`
+/// of the response and the error declared in `requests!`
+pub trait Reply<R>
+where
+    R: Responds,
+{
+    fn into_result(self) -> Result<R::Response, R::Error>;
+}
+
+#[async_trait::async_trait]
+pub trait Eventable: Sized + DeserializeOwned + Serialize {
+    fn path() -> &'static str;
+    /// The schema fingerprinted in [`Eventable::path`]
+    fn schema() -> Schema {
+        Schema::of::<Self>()
+    }
+    async fn publish(&self, client: &Client) -> Result<(), SendError> {
+        client.publish(self).await
+    }
+    fn from_event(event: EventPublish) -> Result<Self, rmp_serde::decode::Error> {
+        decode(&event.data)
+    }
+    fn to_message(&self) -> Message {
+        Message {
+            payload: Payload::EventPublish(EventPublish {
+                data: encode(self),
+                path: Self::path().to_string(),
+                offset: None,
+            }),
+        }
+    }
+}
+
+fn ask<R>(request: &R, id: u32, path: &str, stream: bool, session: bool) -> Message
+where
+    R: Responds + Serialize,
+{
+    Message {
+        payload: Payload::RequestAsk(request_ask(request, id, path, stream, session)),
+    }
+}
+
+fn request_ask<R>(request: &R, id: u32, path: &str, stream: bool, session: bool) -> RequestAsk
+where
+    R: Responds + Serialize,
+{
+    RequestAsk {
+        id,
+        data: encode(request),
+        path: path.to_string(),
+        timeout: None,
+        stream,
+        session,
+        route_key: request.route_key(),
+        idempotent: R::IDEMPOTENT,
+    }
+}
+
+fn reply<R, O>(id: u32, reply: O) -> RequestResponse
+where
+    R: Responds,
+    O: Reply<R>,
+{
+    match reply.into_result() {
+        Ok(response) => RequestResponse {
+            id,
+            status: ResponseStatus::Ok,
+            data: encode(&response),
+        },
+        Err(error) => RequestResponse {
+            id,
+            status: ResponseStatus::Error,
+            data: encode(&error),
+        },
+    }
+}
+
+/// Send each item of `stream` to the caller, ending at the first error
+async fn reply_stream<R, S, O>(
+    id: u32,
+    stream: S,
+    items: &UnboundedSender<StreamItem>,
+) -> RequestResponse
+where
+    R: Responds,
+    S: Stream<Item = O>,
+    O: Reply<R>,
+{
+    let mut stream = std::pin::pin!(stream);
+    while let Some(item) = std::future::poll_fn(|cx| stream.as_mut().poll_next(cx)).await {
+        match item.into_result() {
+            Ok(item) => {
+                let item = StreamItem {
+                    id,
+                    data: encode(&item),
+                };
+                // The responder stops forwarding items once the connection is gone
+                if items.send(item).is_err() {
+                    break;
+                }
+            }
+            Err(error) => {
+                return RequestResponse {
+                    id,
+                    status: ResponseStatus::Error,
+                    data: encode(&error),
+                }
+            }
+        }
+    }
+    RequestResponse {
+        id,
+        status: ResponseStatus::Ok,
+        data: Vec::new(),
+    }
+}
+
+fn bad_request(id: u32, e: &rmp_serde::decode::Error) -> RequestResponse {
+    RequestResponse {
+        id,
+        status: ResponseStatus::BadRequest(e.to_string()),
+        data: Vec::new(),
+    }
+}
+
+/// The data of a successful response, or the error its status describes
+fn response_data<E>(
+    response: RequestResponse,
+    path: &str,
+    schema: fn() -> Schema,
+) -> Result<Vec<u8>, RequestError<E>>
+where
+    E: DeserializeOwned,
+{
+    match response.status {
+        ResponseStatus::Ok => Ok(response.data),
+        ResponseStatus::Error => {
+            Err(decode(&response.data).map_or_else(RequestError::Decode, RequestError::Handler))
+        }
+        ResponseStatus::BadRequest(e) => Err(RequestError::BadRequest(e)),
+        ResponseStatus::NoHandler => Err(RequestError::NoHandler {
+            path: path.to_string(),
+        }),
+        ResponseStatus::HandlerLost => Err(RequestError::HandlerLost {
+            path: path.to_string(),
+        }),
+        ResponseStatus::Timeout => Err(RequestError::Timeout),
+        ResponseStatus::FrameTooLarge(e) => Err(RequestError::FrameTooLarge(e)),
+        ResponseStatus::SchemaMismatch(remote) => {
+            Err(RequestError::SchemaMismatch(SchemaMismatch {
+                path: path.to_string(),
+                local: schema(),
+                remote,
+            }))
+        }
+    }
+}
+
+fn encode<T>(value: &T) -> Vec<u8>
+where
+    T: Serialize + ?Sized,
+{
+    let mut buf = Vec::new();
+    value.serialize(&mut Serializer::new(&mut buf)).unwrap();
+    buf
+}
+
+fn decode<T>(data: &[u8]) -> Result<T, rmp_serde::decode::Error>
+where
+    T: DeserializeOwned,
+{
+    Deserialize::deserialize(&mut Deserializer::new(Cursor::new(data)))
+}
@@ -1,19 +1,52 @@
-use std::{collections::HashMap, future::Future, marker::PhantomData, pin::Pin};
+use std::{
+    collections::HashMap,
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
+use futures_util::{stream::FuturesUnordered, StreamExt};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter},
-    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
+    time::Instant,
 };
 
 use crate::{
-    internals::{Message, Payload, RequestAsk, RequestRegister, RequestResponse},
-    Requestable,
+    auth::authenticate,
+    internals::{
+        Control, Message, Payload, RequestAsk, RequestRegister, RequestResponse, ResponseStatus,
+        StreamItem,
+    },
+    transport::{Connection, FramePart, SkippedFrame, Transport, DEFAULT_MAX_FRAME_SIZE},
+    Address, Backoff, FrameTooLarge, Heartbeat, Schema,
 };
 
 type HandlerFut = Pin<Box<dyn Future<Output = RequestResponse> + Send>>;
-type HandlerFunc = Box<dyn Fn(RequestAsk) -> HandlerFut + Send + Sync>;
+type HandlerFunc = Box<dyn Fn(RequestAsk, Exchange) -> HandlerFut + Send + Sync>;
+
+/// What a handler exchanges with its caller besides the request and the final response
+pub struct Exchange {
+    /// Streaming handlers send their items here before returning the final response
+    pub(crate) items: UnboundedSender<StreamItem>,
+    /// The inputs of a session, closed straight away for other requests
+    pub(crate) inputs: UnboundedReceiver<Vec<u8>>,
+}
+
+/// A request received from the broker that has not been answered yet
+struct Running {
+    /// Where the inputs of a session are routed until the caller closes them
+    inputs: Option<UnboundedSender<Vec<u8>>>,
+    /// Dropped when the caller cancels the request
+    _cancel: oneshot::Sender<()>,
+}
 
 pub struct Handler<T> {
+    pub(crate) path: &'static str,
+    pub(crate) schema: Schema,
     pub(crate) handler: HandlerFunc,
     pub(crate) phantom: PhantomData<T>,
 }
@@ -24,72 +57,413 @@ impl<T> Handler<T> {
     }
 }
 
-#[derive(Default)]
+#[derive(Debug, Clone)]
+pub struct ResponderOptions {
+    /// Pings used to detect a broker that stopped responding
+    pub heartbeat: Heartbeat,
+    /// The shared secret sent to the broker on every connection
+    pub auth: Option<String>,
+    /// The largest frame sent to or accepted from the broker, in bytes
+    pub max_frame_size: usize,
+    /// The most requests handled at the same time, further requests wait for one to finish
+    pub max_in_flight: usize,
+    /// The share of requests sent to this responder when the broker balances by weight
+    pub weight: u32,
+    /// Names this responder to the broker, so requests with a routing key keep going to
+    /// it across reconnects and restarts, a name unique to each [`Responder`] when not set
+    pub instance: Option<String>,
+}
+
+impl Default for ResponderOptions {
+    fn default() -> Self {
+        Self {
+            heartbeat: Heartbeat::default(),
+            auth: None,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_in_flight: 64,
+            weight: 1,
+            instance: None,
+        }
+    }
+}
+
 pub struct Responder {
     handlers: HashMap<String, HandlerFunc>,
+    /// The schemas of the handlers, sent to the broker when they are registered
+    schemas: HashMap<String, Schema>,
+    options: ResponderOptions,
+    /// `options.instance`, or the name made up for this responder
+    instance: String,
+}
+
+impl Default for Responder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A name no other responder is likely to have
+fn unique_instance() -> String {
+    static COUNT: AtomicU32 = AtomicU32::new(0);
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos());
+    format!(
+        "{:x}-{nanos:x}-{:x}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    )
 }
 
 impl Responder {
     pub fn new() -> Self {
+        Self::with_options(ResponderOptions::default())
+    }
+
+    pub fn with_options(options: ResponderOptions) -> Self {
         Self {
             handlers: HashMap::new(),
+            schemas: HashMap::new(),
+            instance: options.instance.clone().unwrap_or_else(unique_instance),
+            options,
         }
     }
 
     pub fn register<R>(&mut self, handler: Handler<R>)
     where
-        R: Requestable + 'static,
+        R: 'static,
     {
+        self.schemas
+            .insert(handler.path.to_string(), handler.schema.clone());
         self.handlers
-            .insert(R::path().to_string(), handler.consume());
+            .insert(handler.path.to_string(), handler.consume());
     }
 
-    pub async fn handle(&self, message: Message) -> RequestResponse {
-        if let Payload::RequestAsk(request) = message.payload {
-            if let Some(handler) = self.handlers.get(&request.path) {
-                return handler(request).await;
-            }
+    /// The paths of the registered handlers
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.handlers.keys().map(String::as_str)
+    }
+
+    /// Answer `request` with the registered handlers, without going through a broker
+    pub async fn handle(&self, request: RequestAsk) -> RequestResponse {
+        let exchange = Exchange {
+            items: unbounded_channel().0,
+            inputs: unbounded_channel().1,
+        };
+        let deadline = request.timeout.map(|timeout| Instant::now() + timeout);
+        self.respond(request, exchange, deadline).await
+    }
+
+    /// Run the handler of a request, giving up at `deadline`
+    async fn respond(
+        &self,
+        request: RequestAsk,
+        exchange: Exchange,
+        deadline: Option<Instant>,
+    ) -> RequestResponse {
+        if let Some(handler) = self.handlers.get(&request.path) {
+            let id = request.id;
+            return match deadline {
+                Some(deadline) if deadline <= Instant::now() => RequestResponse {
+                    id,
+                    status: ResponseStatus::Timeout,
+                    data: Vec::new(),
+                },
+                Some(deadline) => tokio::time::timeout_at(deadline, handler(request, exchange))
+                    .await
+                    .unwrap_or_else(|_| RequestResponse {
+                        id,
+                        status: ResponseStatus::Timeout,
+                        data: Vec::new(),
+                    }),
+                None => handler(request, exchange).await,
+            };
+        }
+        RequestResponse {
+            id: request.id,
+            status: ResponseStatus::NoHandler,
+            data: Vec::new(),
         }
-        unimplemented!()
     }
 
     pub async fn run<A>(&self, address: A) -> Result<(), Box<dyn std::error::Error>>
     where
-        A: ToSocketAddrs,
+        A: Into<Address>,
+    {
+        self.run_with_transport(&address.into()).await
+    }
+
+    /// Run the responder over any transport, such as
+    /// [`TlsTransport`](crate::tls::TlsTransport) when the `tls` feature is enabled
+    pub async fn run_with_transport<T>(
+        &self,
+        transport: &T,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        T: Transport,
     {
-        let mut conn = TcpStream::connect(address).await?;
-        let (read, write) = conn.split();
-        let mut read = BufReader::new(read);
-        let mut write = BufWriter::new(write);
+        let auth = self.options.auth.as_deref();
+        self.serve(self.connect(transport, auth).await?).await
+    }
+
+    pub async fn run_with_auth<A>(
+        &self,
+        address: A,
+        secret: &str,
+    ) -> Result<(), Box<dyn std::error::Error>>
+    where
+        A: Into<Address>,
+    {
+        self.serve(self.connect(&address.into(), Some(secret)).await?)
+            .await
+    }
+
+    async fn connect<T>(&self, transport: &T, auth: Option<&str>) -> std::io::Result<Connection>
+    where
+        T: Transport,
+    {
+        let mut connection = Connection::with_max_frame_size(
+            transport.connect().await?,
+            self.options.max_frame_size,
+        );
+        if let Some(secret) = auth {
+            authenticate(&mut connection, secret).await?;
+        }
+        Ok(connection)
+    }
+
+    /// Run the responder, reconnecting with `backoff` and registering the handlers again
+    /// whenever the connection to the broker is lost
+    pub async fn run_forever<A>(&self, address: A, backoff: Backoff)
+    where
+        A: Into<Address>,
+    {
+        self.run_forever_with_transport(&address.into(), backoff)
+            .await;
+    }
+
+    /// [`Responder::run_forever`] over any transport
+    pub async fn run_forever_with_transport<T>(&self, transport: &T, backoff: Backoff)
+    where
+        T: Transport,
+    {
+        let mut attempt = 0u32;
+        loop {
+            if let Ok(conn) = self.connect(transport, self.options.auth.as_deref()).await {
+                attempt = 0;
+                let _ = self.serve(conn).await;
+            } else {
+                attempt = attempt.saturating_add(1);
+            }
+            tokio::time::sleep(backoff.delay(attempt)).await;
+        }
+    }
+
+    async fn serve(&self, conn: Connection) -> Result<(), Box<dyn std::error::Error>> {
+        let (mut read, mut write) = conn.into_split();
         for handler in &self.handlers {
             let message = Message {
                 payload: Payload::RequestRegister(RequestRegister {
                     path: handler.0.to_string(),
+                    weight: self.options.weight,
+                    schema: self.schemas.get(handler.0).cloned(),
+                    instance: Some(self.instance.clone()),
                 }),
             };
-            let bytes = &message.to_bytes();
-            write.write_u32(bytes.len() as u32).await?;
-            write.write_all(bytes).await?;
-            write.flush().await?;
+            write.write(&message).await?;
         }
-        let mut buffer = [0; 1024];
-        loop {
-            let expect = read.read_u32().await?;
-            if expect == 0 {
-                break;
+        let missed = AtomicU32::new(0);
+        let (outgoing, mut pending_writes) = unbounded_channel();
+        let outgoing: &Outgoing = &outgoing;
+        let (queue, mut requests) = unbounded_channel();
+        // Inputs and cancellations are routed by the reader, so they also reach requests
+        // still waiting in the queue
+        let running = std::sync::Mutex::new(HashMap::new());
+        // Keep reading while handlers run, so pings are still answered
+        let reader = async {
+            loop {
+                let message = match read.read().await {
+                    Ok(Some(message)) => message,
+                    Ok(None) => break,
+                    Err(e) => {
+                        let skipped = SkippedFrame::from_io(&e).ok_or(e)?;
+                        missed.store(0, Ordering::Relaxed);
+                        if let FramePart::Request(id) = skipped.part {
+                            // A session can not go on without one of its inputs
+                            running.lock().unwrap().remove(&id);
+                            let response = RequestResponse {
+                                id,
+                                status: ResponseStatus::FrameTooLarge(skipped.error),
+                                data: Vec::new(),
+                            };
+                            write_message(
+                                outgoing,
+                                Message::new(Payload::RequestResponse(response)),
+                            )
+                            .await?;
+                        }
+                        continue;
+                    }
+                };
+                missed.store(0, Ordering::Relaxed);
+                match message.payload {
+                    Payload::Control(Control::Ping) => {
+                        write_message(outgoing, Message::new(Payload::Control(Control::Pong)))
+                            .await?;
+                    }
+                    Payload::Control(Control::Disconnect) => break,
+                    Payload::Control(Control::Cancel(id)) => {
+                        running.lock().unwrap().remove(&id);
+                    }
+                    Payload::Control(_) => {}
+                    Payload::RequestAsk(request) => {
+                        // Time spent waiting in the queue counts towards the timeout
+                        let deadline = request.timeout.map(|timeout| Instant::now() + timeout);
+                        let (input, inputs) = unbounded_channel();
+                        let (cancel, cancelled) = oneshot::channel();
+                        running.lock().unwrap().insert(
+                            request.id,
+                            Running {
+                                inputs: request.session.then_some(input),
+                                _cancel: cancel,
+                            },
+                        );
+                        queue.send((request, deadline, inputs, cancelled))?;
+                    }
+                    Payload::SessionSend(item) => {
+                        let running = running.lock().unwrap();
+                        if let Some(input) = running.get(&item.id).and_then(|r| r.inputs.as_ref()) {
+                            let _ = input.send(item.data);
+                        }
+                    }
+                    Payload::SessionClose(close) => {
+                        if let Some(running) = running.lock().unwrap().get_mut(&close.id) {
+                            running.inputs = None;
+                        }
+                    }
+                    _ => {}
+                }
             }
-            let buf = &mut buffer[..expect as usize];
-            let _ = read.read_exact(buf).await?;
-            let message = Message::from_bytes(buf);
-            let response = self.handle(message).await;
-            let message = Message {
-                payload: Payload::RequestResponse(response),
-            };
-            let bytes = &message.to_bytes();
-            write.write_u32(bytes.len() as u32).await?;
-            write.write_all(bytes).await?;
-            write.flush().await?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        };
+        // Frames are written by a single task, so a handler that is aborted never cuts one short
+        let writer = async {
+            while let Some((message, written)) = pending_writes.recv().await {
+                let result = write.write(&message).await;
+                let lost = result
+                    .as_ref()
+                    .is_err_and(|e| FrameTooLarge::from_io(e).is_none());
+                let _ = written.send(result);
+                if lost {
+                    return Err("the connection to the broker was lost".into());
+                }
+            }
+            Ok(())
+        };
+        let max_in_flight = self.options.max_in_flight.max(1);
+        let worker = async {
+            let mut in_flight = FuturesUnordered::new();
+            loop {
+                tokio::select! {
+                    Some((request, deadline, inputs, cancelled)) = requests.recv(),
+                        if in_flight.len() < max_in_flight =>
+                    {
+                        in_flight.push(self.answer(request, deadline, inputs, cancelled, outgoing));
+                    }
+                    Some(id) = in_flight.next() => {
+                        running.lock().unwrap().remove(&id?);
+                    }
+                    else => return Ok(()),
+                }
+            }
+        };
+        let heartbeat = async {
+            self.options
+                .heartbeat
+                .monitor(&missed, || async {
+                    write_message(outgoing, Message::new(Payload::Control(Control::Ping)))
+                        .await
+                        .is_ok()
+                })
+                .await;
+            Err("the broker stopped responding".into())
+        };
+        tokio::select! {
+            res = reader => res,
+            res = writer => res,
+            res = worker => res,
+            res = heartbeat => res,
         }
-        Ok(())
     }
+
+    /// Run the handler of a request and send its response, returning the id of the request
+    async fn answer(
+        &self,
+        request: RequestAsk,
+        deadline: Option<Instant>,
+        inputs: UnboundedReceiver<Vec<u8>>,
+        cancelled: oneshot::Receiver<()>,
+        outgoing: &Outgoing,
+    ) -> std::io::Result<u32> {
+        let stream = request.stream;
+        let id = request.id;
+        let (items, mut pending_items) = unbounded_channel();
+        let forward = async {
+            while let Some(item) = pending_items.recv().await {
+                if let Err(e) =
+                    write_message(outgoing, Message::new(Payload::StreamItem(item))).await
+                {
+                    // Dropping the receiver stops the handler, its items can not be sent
+                    return FrameTooLarge::from_io(&e).ok_or(e).map(Some);
+                }
+            }
+            Ok(None)
+        };
+        let exchange = Exchange { items, inputs };
+        let respond = async {
+            tokio::select! {
+                response = self.respond(request, exchange, deadline) => Some(response),
+                _ = cancelled => None,
+            }
+        };
+        let (response, too_large) = tokio::join!(respond, forward);
+        let too_large = too_large?;
+        // The broker has already forgotten a cancelled request
+        let Some(response) = response else {
+            return Ok(id);
+        };
+        let rejected = |too_large| RequestResponse {
+            id,
+            status: ResponseStatus::FrameTooLarge(too_large),
+            data: Vec::new(),
+        };
+        let end = |response| {
+            Message::new(if stream {
+                Payload::StreamEnd(response)
+            } else {
+                Payload::RequestResponse(response)
+            })
+        };
+        let response = match too_large {
+            Some(e) => rejected(e),
+            None => response,
+        };
+        if let Err(e) = write_message(outgoing, end(response)).await {
+            // Nothing was written, so the caller can still be told why
+            let e = FrameTooLarge::from_io(&e).ok_or(e)?;
+            write_message(outgoing, end(rejected(e))).await?;
+        }
+        Ok(id)
+    }
+}
+
+/// Frames waiting for the writer, each with where to report the result of writing it
+type Outgoing = UnboundedSender<(Message, oneshot::Sender<std::io::Result<()>>)>;
+
+/// Queue a frame for the writer and wait until it is written
+async fn write_message(outgoing: &Outgoing, message: Message) -> std::io::Result<()> {
+    let lost = || std::io::Error::from(std::io::ErrorKind::BrokenPipe);
+    let (written, result) = oneshot::channel();
+    outgoing.send((message, written)).map_err(|_| lost())?;
+    result.await.map_err(|_| lost())?
 }
@@ -0,0 +1,91 @@
+use std::{
+    marker::PhantomData,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use futures_core::Stream;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::{client::CancelOnDrop, internals::RequestResponse, RequestError, Responds, Schema};
+
+type Item<R> = Result<<R as Responds>::Response, RequestError<<R as Responds>::Error>>;
+
+/// What the client receives for a streamed request
+pub(crate) enum StreamFrame {
+    Item(Vec<u8>),
+    End(RequestResponse),
+}
+
+/// The responses to a [`crate::Streamable`] request, created by [`crate::Client::request_stream`]
+///
+/// Dropping the stream before it ends cancels the request
+pub struct ResponseStream<R> {
+    receiver: UnboundedReceiver<StreamFrame>,
+    path: &'static str,
+    /// The caller's schema of the request, for a [`crate::SchemaMismatch`]
+    schema: fn() -> Schema,
+    done: bool,
+    _cancel: CancelOnDrop,
+    phantom: PhantomData<fn() -> R>,
+}
+
+impl<R> ResponseStream<R>
+where
+    R: Responds,
+{
+    pub(crate) fn new(
+        receiver: UnboundedReceiver<StreamFrame>,
+        path: &'static str,
+        schema: fn() -> Schema,
+        cancel: CancelOnDrop,
+    ) -> Self {
+        Self {
+            receiver,
+            path,
+            schema,
+            done: false,
+            _cancel: cancel,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Wait for the next response, `None` once the handler has finished
+    ///
+    /// An error ends the stream
+    pub async fn next(&mut self) -> Option<Item<R>> {
+        std::future::poll_fn(|cx| self.poll_item(cx)).await
+    }
+
+    fn poll_item(&mut self, cx: &mut Context<'_>) -> Poll<Option<Item<R>>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        Poll::Ready(match ready!(self.receiver.poll_recv(cx)) {
+            Some(StreamFrame::Item(data)) => {
+                Some(crate::decode(&data).map_err(RequestError::Decode))
+            }
+            Some(StreamFrame::End(response)) => {
+                self.done = true;
+                crate::response_data(response, self.path, self.schema)
+                    .err()
+                    .map(Err)
+            }
+            None => {
+                self.done = true;
+                Some(Err(RequestError::ConnectionLost))
+            }
+        })
+    }
+}
+
+impl<R> Stream for ResponseStream<R>
+where
+    R: Responds,
+{
+    type Item = Item<R>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_item(cx)
+    }
+}
@@ -0,0 +1,1062 @@
+use std::collections::HashMap;
+
+use serde::{
+    de::{
+        self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess,
+        SeqAccess, VariantAccess, Visitor,
+    },
+    Deserialize, Serialize,
+};
+
+use crate::Responds;
+
+/// The serde shape of a type, traced by [`Schema::of`]
+///
+/// The names of types are not part of a schema, the names of fields and variants are.
+/// The fingerprint of a schema is the 64 bit FNV-1a hash of its canonical encoding: a tag
+/// byte for each node in the order of the variants below starting at 0, followed by the
+/// children of the node. Lengths, depths and names are written before the children, as
+/// little endian `u32`s, and names as UTF-8 bytes after their length.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Schema {
+    Bool,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    F32,
+    F64,
+    Char,
+    Str,
+    Bytes,
+    Unit,
+    /// A part of the type that could not be traced, such as one deserialized with
+    /// `deserialize_any` or one following a value that failed to deserialize
+    Any,
+    Option(Box<Schema>),
+    Seq(Box<Schema>),
+    Map(Box<Schema>, Box<Schema>),
+    Tuple(Vec<Schema>),
+    UnitStruct,
+    Newtype(Box<Schema>),
+    TupleStruct(Vec<Schema>),
+    Struct(Vec<(String, Schema)>),
+    /// The variants, each a [`Schema::UnitStruct`], [`Schema::Newtype`],
+    /// [`Schema::TupleStruct`] or [`Schema::Struct`]
+    Enum(Vec<(String, Schema)>),
+    /// A recursive reference to an enclosing struct or enum, 0 being the innermost one
+    Recursive(u32),
+}
+
+impl Schema {
+    /// Trace the schema of `T` by deserializing it from sample values, exploring every
+    /// variant of its enums
+    ///
+    /// Samples start with zeros, empty strings and sequences of one element. A value that
+    /// is rejected is retried with the other samples of its type, such as 1 for a
+    /// `NonZeroU32` or a UUID for a string, and types that reject all of them are only
+    /// traced up to the rejected value. Types are traced as they are sent, in the
+    /// compact form of a non human readable format
+    pub fn of<T>() -> Self
+    where
+        T: DeserializeOwned,
+    {
+        let mut tracer = Tracer::default();
+        let mut progress = None;
+        while progress != Some(tracer.progress()) {
+            progress = Some(tracer.progress());
+            tracer.stack.clear();
+            tracer.cut = 0;
+            tracer.last = None;
+            // A value that fails to deserialize leaves the rest of the pass untraced, the
+            // next pass tries another sample for the value read last
+            if T::deserialize(Tracing {
+                tracer: &mut tracer,
+                site: Vec::new(),
+            })
+            .is_err()
+            {
+                tracer.reject();
+            }
+        }
+        tracer.assemble(&mut Vec::new())
+    }
+
+    /// A stable hash of the schema, see [`Schema`] for how it is computed
+    pub fn fingerprint(&self) -> u64 {
+        let mut bytes = Vec::new();
+        self.encode(&mut bytes);
+        crate::internals::fnv1a(&bytes)
+    }
+
+    /// Describe the first difference between this schema and `other`, such as
+    /// "adds `request.b`", `None` when they are the same
+    ///
+    /// Places are named by their fields, `::` for the variants of an enum, `[]` for the
+    /// elements of a sequence and `.0` for the elements of a tuple
+    pub fn difference(&self, other: &Self) -> Option<String> {
+        self.difference_at(other, "")
+    }
+
+    fn difference_at(&self, other: &Self, at: &str) -> Option<String> {
+        fn join(at: &str, separator: &str, name: &str) -> String {
+            if at.is_empty() {
+                name.to_string()
+            } else {
+                format!("{at}{separator}{name}")
+            }
+        }
+        fn place(at: &str) -> String {
+            if at.is_empty() {
+                "the schema".to_string()
+            } else {
+                format!("`{at}`")
+            }
+        }
+        if self == other {
+            return None;
+        }
+        match (self, other) {
+            (Self::Option(a), Self::Option(b)) | (Self::Newtype(a), Self::Newtype(b)) => {
+                a.difference_at(b, at)
+            }
+            (Self::Seq(a), Self::Seq(b)) => a.difference_at(b, &format!("{at}[]")),
+            (Self::Map(ak, av), Self::Map(bk, bv)) => ak
+                .difference_at(bk, &format!("{at}[key]"))
+                .or_else(|| av.difference_at(bv, &format!("{at}[value]"))),
+            (Self::Tuple(a), Self::Tuple(b)) | (Self::TupleStruct(a), Self::TupleStruct(b)) => {
+                if a.len() != b.len() {
+                    return Some(format!(
+                        "has {} elements instead of {} in {}",
+                        b.len(),
+                        a.len(),
+                        place(at)
+                    ));
+                }
+                a.iter()
+                    .zip(b)
+                    .enumerate()
+                    .find_map(|(i, (a, b))| a.difference_at(b, &join(at, ".", &i.to_string())))
+            }
+            (Self::Struct(a), Self::Struct(b)) | (Self::Enum(a), Self::Enum(b)) => {
+                let (separator, members) = if matches!(self, Self::Enum(_)) {
+                    ("::", "variants")
+                } else {
+                    (".", "fields")
+                };
+                let find = |named: &[(String, Schema)], name: &str| {
+                    named.iter().position(|(n, _)| n == name)
+                };
+                for (name, schema) in a {
+                    match find(b, name) {
+                        Some(i) => {
+                            if let Some(difference) =
+                                schema.difference_at(&b[i].1, &join(at, separator, name))
+                            {
+                                return Some(difference);
+                            }
+                        }
+                        None => return Some(format!("removes `{}`", join(at, separator, name))),
+                    }
+                }
+                if let Some((name, _)) = b.iter().find(|(name, _)| find(a, name).is_none()) {
+                    return Some(format!("adds `{}`", join(at, separator, name)));
+                }
+                Some(format!("reorders the {members} of {}", place(at)))
+            }
+            _ => Some(format!(
+                "has {} instead of {} in {}",
+                other.name(),
+                self.name(),
+                place(at)
+            )),
+        }
+    }
+
+    /// What kind of schema this is, for [`Schema::difference`]
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Bool => "bool",
+            Self::I8 => "i8",
+            Self::I16 => "i16",
+            Self::I32 => "i32",
+            Self::I64 => "i64",
+            Self::I128 => "i128",
+            Self::U8 => "u8",
+            Self::U16 => "u16",
+            Self::U32 => "u32",
+            Self::U64 => "u64",
+            Self::U128 => "u128",
+            Self::F32 => "f32",
+            Self::F64 => "f64",
+            Self::Char => "char",
+            Self::Str => "a string",
+            Self::Bytes => "bytes",
+            Self::Unit => "unit",
+            Self::Any => "an untraced type",
+            Self::Option(_) => "an option",
+            Self::Seq(_) => "a sequence",
+            Self::Map(_, _) => "a map",
+            Self::Tuple(_) => "a tuple",
+            Self::UnitStruct => "a unit struct",
+            Self::Newtype(_) => "a newtype",
+            Self::TupleStruct(_) => "a tuple struct",
+            Self::Struct(_) => "a struct",
+            Self::Enum(_) => "an enum",
+            Self::Recursive(_) => "a recursive type",
+        }
+    }
+
+    fn tag(&self) -> u8 {
+        match self {
+            Self::Bool => 0,
+            Self::I8 => 1,
+            Self::I16 => 2,
+            Self::I32 => 3,
+            Self::I64 => 4,
+            Self::I128 => 5,
+            Self::U8 => 6,
+            Self::U16 => 7,
+            Self::U32 => 8,
+            Self::U64 => 9,
+            Self::U128 => 10,
+            Self::F32 => 11,
+            Self::F64 => 12,
+            Self::Char => 13,
+            Self::Str => 14,
+            Self::Bytes => 15,
+            Self::Unit => 16,
+            Self::Any => 17,
+            Self::Option(_) => 18,
+            Self::Seq(_) => 19,
+            Self::Map(_, _) => 20,
+            Self::Tuple(_) => 21,
+            Self::UnitStruct => 22,
+            Self::Newtype(_) => 23,
+            Self::TupleStruct(_) => 24,
+            Self::Struct(_) => 25,
+            Self::Enum(_) => 26,
+            Self::Recursive(_) => 27,
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        fn len(out: &mut Vec<u8>, len: usize) {
+            out.extend_from_slice(&u32::try_from(len).unwrap_or(u32::MAX).to_le_bytes());
+        }
+        out.push(self.tag());
+        match self {
+            Self::Option(inner) | Self::Seq(inner) | Self::Newtype(inner) => inner.encode(out),
+            Self::Map(key, value) => {
+                key.encode(out);
+                value.encode(out);
+            }
+            Self::Tuple(items) | Self::TupleStruct(items) => {
+                len(out, items.len());
+                for item in items {
+                    item.encode(out);
+                }
+            }
+            Self::Struct(named) | Self::Enum(named) => {
+                len(out, named.len());
+                for (name, schema) in named {
+                    len(out, name.len());
+                    out.extend_from_slice(name.as_bytes());
+                    schema.encode(out);
+                }
+            }
+            Self::Recursive(depth) => out.extend_from_slice(&depth.to_le_bytes()),
+            _ => {}
+        }
+    }
+}
+
+/// The schema of a request: a struct of the `request`, the `input` of a client stream,
+/// the `response`, a `stream` unit when the response is streamed, and the `error`
+pub(crate) fn request<R>(input: Option<Schema>, stream: bool) -> Schema
+where
+    R: Responds + DeserializeOwned,
+{
+    let mut fields = vec![("request".to_string(), Schema::of::<R>())];
+    if let Some(input) = input {
+        fields.push(("input".to_string(), input));
+    }
+    fields.push(("response".to_string(), Schema::of::<R::Response>()));
+    if stream {
+        fields.push(("stream".to_string(), Schema::Unit));
+    }
+    fields.push(("error".to_string(), Schema::of::<R::Error>()));
+    Schema::Struct(fields)
+}
+
+/// What was seen at a site while tracing, the children of a site are found
+/// at the site followed by their index
+#[derive(Debug)]
+enum Node {
+    Leaf(Schema),
+    Option,
+    Seq,
+    Map,
+    Tuple(usize),
+    UnitStruct,
+    Newtype,
+    TupleStruct(usize),
+    Struct(&'static [&'static str]),
+    /// The variants, and which of them have been traced
+    Enum(&'static [&'static str], Vec<bool>),
+}
+
+#[derive(Debug, Default)]
+struct Tracer {
+    /// The nodes by their path from the root
+    nodes: HashMap<Vec<u32>, Node>,
+    /// The visitors of the structs and enums being traced, identifying recursive types
+    stack: Vec<&'static str>,
+    /// How many recursive types are being traced, their shape is already known
+    /// so nothing is recorded and the smallest values are produced
+    cut: usize,
+    /// The sample produced at each site whose earlier samples were rejected
+    samples: HashMap<Vec<u32>, usize>,
+    /// The last site a sample was produced at in this pass, and how many samples it has
+    last: Option<(Vec<u32>, usize)>,
+}
+
+/// The deepest nesting of structs and enums traced, for recursive enums whose
+/// first variant is recursive as well
+const MAX_DEPTH: usize = 256;
+
+impl Tracer {
+    fn record(&mut self, site: &[u32], node: Node) {
+        if self.cut > 0 {
+            return;
+        }
+        let node = match (self.nodes.remove(site), node) {
+            (Some(Node::Enum(_, old)), Node::Enum(variants, new)) => Node::Enum(
+                variants,
+                old.iter().zip(new).map(|(old, new)| *old || new).collect(),
+            ),
+            (_, node) => node,
+        };
+        self.nodes.insert(site.to_vec(), node);
+    }
+
+    /// The index of the sample to produce at `site`, out of `count`
+    fn sample(&mut self, site: &[u32], count: usize) -> usize {
+        self.last = Some((site.to_vec(), count));
+        self.samples.get(site).copied().unwrap_or(0)
+    }
+
+    /// Move on to the next sample of the value produced last, which is the one most
+    /// likely to have failed the pass
+    fn reject(&mut self) {
+        if let Some((site, count)) = self.last.take() {
+            let sample = self.samples.entry(site).or_insert(0);
+            if *sample + 1 < count {
+                *sample += 1;
+            }
+        }
+    }
+
+    /// How much has been traced, tracing is done once a pass adds nothing
+    fn progress(&self) -> (usize, usize, usize) {
+        let explored = self
+            .nodes
+            .values()
+            .map(|node| match node {
+                Node::Enum(_, explored) => explored.iter().filter(|x| **x).count(),
+                _ => 0,
+            })
+            .sum();
+        (self.nodes.len(), explored, self.samples.values().sum())
+    }
+
+    /// Whether an enum at or below `site` has a variant left to trace
+    fn incomplete(&self, site: &[u32]) -> bool {
+        self.nodes.iter().any(|(path, node)| {
+            path.starts_with(site)
+                && matches!(node, Node::Enum(_, explored) if explored.contains(&false))
+        })
+    }
+
+    /// The variant to trace for the enum at `site`: one not traced yet, then one
+    /// with enums left to trace inside it
+    fn pick_variant(&self, site: &[u32], count: usize) -> u32 {
+        if self.cut > 0 {
+            return 0;
+        }
+        let explored = match self.nodes.get(site) {
+            Some(Node::Enum(_, explored)) => explored.as_slice(),
+            _ => &[],
+        };
+        let mut child = site.to_vec();
+        (0..count)
+            .find(|&i| !explored.get(i).copied().unwrap_or(false))
+            .or_else(|| {
+                (0..count).find(|&i| {
+                    child.push(i as u32);
+                    let incomplete = self.incomplete(&child);
+                    child.pop();
+                    incomplete
+                })
+            })
+            .map_or(0, |i| i as u32)
+    }
+
+    fn assemble(&self, site: &mut Vec<u32>) -> Schema {
+        let node = self.nodes.get(site.as_slice());
+        let mut child = |i: usize| {
+            site.push(i as u32);
+            let schema = self.assemble(site);
+            site.pop();
+            schema
+        };
+        match node {
+            None => Schema::Any,
+            Some(Node::Leaf(schema)) => schema.clone(),
+            Some(Node::Option) => Schema::Option(Box::new(child(0))),
+            Some(Node::Seq) => Schema::Seq(Box::new(child(0))),
+            Some(Node::Map) => Schema::Map(Box::new(child(0)), Box::new(child(1))),
+            Some(Node::Tuple(len)) => Schema::Tuple((0..*len).map(child).collect()),
+            Some(Node::UnitStruct) => Schema::UnitStruct,
+            Some(Node::Newtype) => Schema::Newtype(Box::new(child(0))),
+            Some(Node::TupleStruct(len)) => Schema::TupleStruct((0..*len).map(child).collect()),
+            Some(Node::Struct(fields)) => Schema::Struct(
+                fields
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| ((*name).to_string(), child(i)))
+                    .collect(),
+            ),
+            Some(Node::Enum(variants, _)) => Schema::Enum(
+                variants
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| ((*name).to_string(), child(i)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// The error of a value rejected while tracing
+#[derive(Debug)]
+struct TraceError(String);
+
+impl std::fmt::Display for TraceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for TraceError {}
+
+impl de::Error for TraceError {
+    fn custom<T>(msg: T) -> Self
+    where
+        T: std::fmt::Display,
+    {
+        Self(msg.to_string())
+    }
+}
+
+/// Deserializes the sample value at a site, recording its shape
+struct Tracing<'a> {
+    tracer: &'a mut Tracer,
+    site: Vec<u32>,
+}
+
+impl<'a> Tracing<'a> {
+    fn leaf(self, schema: Schema) {
+        self.tracer.record(&self.site, Node::Leaf(schema));
+    }
+
+    fn elements(self, len: usize) -> Elements<'a> {
+        Elements {
+            tracer: self.tracer,
+            site: self.site,
+            next: 0,
+            len: len as u32,
+        }
+    }
+
+    /// Trace a struct or enum, which is recursive if its visitor is already being traced
+    fn named<V, T>(
+        self,
+        f: impl FnOnce(Tracing<'_>) -> Result<T, TraceError>,
+    ) -> Result<T, TraceError> {
+        let Tracing { tracer, site } = self;
+        let key = std::any::type_name::<V>();
+        if tracer.stack.len() >= MAX_DEPTH {
+            return Err(TraceError("recursion too deep".to_string()));
+        }
+        let recursive = tracer.stack.iter().rev().position(|k| *k == key);
+        if let Some(depth) = recursive {
+            tracer.record(&site, Node::Leaf(Schema::Recursive(depth as u32)));
+            tracer.cut += 1;
+        }
+        tracer.stack.push(key);
+        let result = f(Tracing {
+            tracer: &mut *tracer,
+            site,
+        });
+        tracer.stack.pop();
+        if recursive.is_some() {
+            tracer.cut -= 1;
+        }
+        result
+    }
+}
+
+macro_rules! leaves {
+    ($($method:ident $schema:ident $visit:ident $samples:expr;)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+            where
+                V: Visitor<'de>,
+            {
+                let samples = $samples;
+                let sample = self.tracer.sample(&self.site, samples.len());
+                self.leaf(Schema::$schema);
+                visitor.$visit(samples[sample])
+            }
+        )*
+    };
+}
+
+/// The strings tried in turn, for types parsed from strings
+const STRINGS: [&str; 6] = [
+    "",
+    "0",
+    "a",
+    "0.0.0.0",
+    "1970-01-01T00:00:00Z",
+    "00000000-0000-0000-0000-000000000000",
+];
+
+/// The bytes tried in turn, for types of a fixed length such as UUIDs
+const BYTES: [&[u8]; 4] = [&[], &[0; 16], &[0; 32], &[0; 64]];
+
+impl<'de> de::Deserializer<'de> for Tracing<'_> {
+    type Error = TraceError;
+
+    leaves! {
+        deserialize_bool Bool visit_bool [false, true];
+        deserialize_i8 I8 visit_i8 [0, 1];
+        deserialize_i16 I16 visit_i16 [0, 1];
+        deserialize_i32 I32 visit_i32 [0, 1];
+        deserialize_i64 I64 visit_i64 [0, 1];
+        deserialize_i128 I128 visit_i128 [0, 1];
+        deserialize_u8 U8 visit_u8 [0, 1];
+        deserialize_u16 U16 visit_u16 [0, 1];
+        deserialize_u32 U32 visit_u32 [0, 1];
+        deserialize_u64 U64 visit_u64 [0, 1];
+        deserialize_u128 U128 visit_u128 [0, 1];
+        deserialize_f32 F32 visit_f32 [0.0, 1.0];
+        deserialize_f64 F64 visit_f64 [0.0, 1.0];
+        deserialize_char Char visit_char ['0', 'a'];
+        deserialize_str Str visit_str STRINGS;
+        deserialize_string Str visit_str STRINGS;
+        deserialize_identifier Str visit_str [""];
+        deserialize_bytes Bytes visit_bytes BYTES;
+        deserialize_byte_buf Bytes visit_bytes BYTES;
+    }
+
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.leaf(Schema::Any);
+        visitor.visit_unit()
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.deserialize_any(visitor)
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.leaf(Schema::Unit);
+        visitor.visit_unit()
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.tracer.record(&self.site, Node::Option);
+        if self.tracer.cut > 0 {
+            visitor.visit_none()
+        } else {
+            let mut site = self.site;
+            site.push(0);
+            visitor.visit_some(Tracing {
+                tracer: self.tracer,
+                site,
+            })
+        }
+    }
+
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.tracer.record(&self.site, Node::Seq);
+        let len = usize::from(self.tracer.cut == 0);
+        visitor.visit_seq(self.elements(len))
+    }
+
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.tracer.record(&self.site, Node::Tuple(len));
+        visitor.visit_seq(self.elements(len))
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.tracer.record(&self.site, Node::Map);
+        let remaining = self.tracer.cut == 0;
+        visitor.visit_map(Entries {
+            tracer: self.tracer,
+            site: self.site,
+            remaining,
+        })
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.tracer.record(&self.site, Node::UnitStruct);
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.named::<V, _>(|this| {
+            this.tracer.record(&this.site, Node::Newtype);
+            let mut site = this.site;
+            site.push(0);
+            visitor.visit_newtype_struct(Tracing {
+                tracer: this.tracer,
+                site,
+            })
+        })
+    }
+
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.named::<V, _>(|this| {
+            this.tracer.record(&this.site, Node::TupleStruct(len));
+            visitor.visit_seq(this.elements(len))
+        })
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.named::<V, _>(|this| {
+            this.tracer.record(&this.site, Node::Struct(fields));
+            visitor.visit_seq(this.elements(fields.len()))
+        })
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.named::<V, _>(|this| {
+            let index = this.tracer.pick_variant(&this.site, variants.len());
+            let mut explored = vec![false; variants.len()];
+            if let Some(explored) = explored.get_mut(index as usize) {
+                *explored = true;
+            }
+            this.tracer
+                .record(&this.site, Node::Enum(variants, explored));
+            if variants.is_empty() {
+                return Err(TraceError("the enum has no variants".to_string()));
+            }
+            visitor.visit_enum(Variant {
+                tracer: this.tracer,
+                site: this.site,
+                index,
+            })
+        })
+    }
+}
+
+/// The elements of a sequence, tuple or struct
+struct Elements<'a> {
+    tracer: &'a mut Tracer,
+    site: Vec<u32>,
+    next: u32,
+    len: u32,
+}
+
+impl<'de> SeqAccess<'de> for Elements<'_> {
+    type Error = TraceError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        if self.next == self.len {
+            return Ok(None);
+        }
+        let mut site = self.site.clone();
+        site.push(self.next);
+        self.next += 1;
+        seed.deserialize(Tracing {
+            tracer: &mut *self.tracer,
+            site,
+        })
+        .map(Some)
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some((self.len - self.next) as usize)
+    }
+}
+
+/// The single entry of a map
+struct Entries<'a> {
+    tracer: &'a mut Tracer,
+    site: Vec<u32>,
+    remaining: bool,
+}
+
+impl<'de> MapAccess<'de> for Entries<'_> {
+    type Error = TraceError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>, Self::Error>
+    where
+        K: DeserializeSeed<'de>,
+    {
+        if !self.remaining {
+            return Ok(None);
+        }
+        let mut site = self.site.clone();
+        site.push(0);
+        seed.deserialize(Tracing {
+            tracer: &mut *self.tracer,
+            site,
+        })
+        .map(Some)
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value, Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        self.remaining = false;
+        let mut site = self.site.clone();
+        site.push(1);
+        seed.deserialize(Tracing {
+            tracer: &mut *self.tracer,
+            site,
+        })
+    }
+}
+
+/// The variant chosen for an enum, its content is traced below the enum's site
+struct Variant<'a> {
+    tracer: &'a mut Tracer,
+    site: Vec<u32>,
+    index: u32,
+}
+
+impl<'a> Variant<'a> {
+    fn content(mut self, node: Node) -> Tracing<'a> {
+        self.site.push(self.index);
+        self.tracer.record(&self.site, node);
+        Tracing {
+            tracer: self.tracer,
+            site: self.site,
+        }
+    }
+}
+
+impl<'de> EnumAccess<'de> for Variant<'_> {
+    type Error = TraceError;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Self::Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let value = seed.deserialize(self.index.into_deserializer())?;
+        Ok((value, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for Variant<'_> {
+    type Error = TraceError;
+
+    fn unit_variant(self) -> Result<(), Self::Error> {
+        self.content(Node::UnitStruct);
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        let Tracing { tracer, mut site } = self.content(Node::Newtype);
+        site.push(0);
+        seed.deserialize(Tracing { tracer, site })
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(self.content(Node::TupleStruct(len)).elements(len))
+    }
+
+    fn struct_variant<V>(
+        self,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_seq(self.content(Node::Struct(fields)).elements(fields.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct User {
+        id: u32,
+        name: String,
+        tags: Vec<String>,
+        role: Role,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum Role {
+        Guest,
+        Member(u64),
+        Admin { level: u8, scope: Option<Role2> },
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum Role2 {
+        Read,
+        Write(bool),
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Tree {
+        value: i32,
+        children: Vec<Tree>,
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    enum Expr {
+        Lit(i64),
+        Add(Box<Expr>, Box<Expr>),
+    }
+
+    #[derive(Deserialize)]
+    enum Empty {}
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Validated {
+        id: std::num::NonZeroU32,
+        addr: std::net::SocketAddr,
+        inner: Struct,
+    }
+
+    fn named(fields: &[(&str, Schema)]) -> Vec<(String, Schema)> {
+        fields
+            .iter()
+            .map(|(name, schema)| ((*name).to_string(), schema.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn structs_and_enums() {
+        let role2 = Schema::Enum(named(&[
+            ("Read", Schema::UnitStruct),
+            ("Write", Schema::Newtype(Box::new(Schema::Bool))),
+        ]));
+        let role = Schema::Enum(named(&[
+            ("Guest", Schema::UnitStruct),
+            ("Member", Schema::Newtype(Box::new(Schema::U64))),
+            (
+                "Admin",
+                Schema::Struct(named(&[
+                    ("level", Schema::U8),
+                    ("scope", Schema::Option(Box::new(role2))),
+                ])),
+            ),
+        ]));
+        assert_eq!(
+            Schema::of::<User>(),
+            Schema::Struct(named(&[
+                ("id", Schema::U32),
+                ("name", Schema::Str),
+                ("tags", Schema::Seq(Box::new(Schema::Str))),
+                ("role", role),
+            ]))
+        );
+        assert_eq!(
+            Schema::of::<(u8, Option<char>)>(),
+            Schema::Tuple(vec![Schema::U8, Schema::Option(Box::new(Schema::Char))])
+        );
+    }
+
+    #[test]
+    fn recursive() {
+        assert_eq!(
+            Schema::of::<Tree>(),
+            Schema::Struct(named(&[
+                ("value", Schema::I32),
+                ("children", Schema::Seq(Box::new(Schema::Recursive(0)))),
+            ]))
+        );
+        assert_eq!(
+            Schema::of::<Expr>(),
+            Schema::Enum(named(&[
+                ("Lit", Schema::Newtype(Box::new(Schema::I64))),
+                (
+                    "Add",
+                    Schema::TupleStruct(vec![Schema::Recursive(0), Schema::Recursive(0)])
+                ),
+            ]))
+        );
+    }
+
+    #[test]
+    fn uninhabited() {
+        assert_eq!(Schema::of::<Empty>(), Schema::Enum(Vec::new()));
+    }
+
+    #[test]
+    fn rejected_samples() {
+        // Fields after one that rejects the first sample are still traced
+        let Schema::Struct(fields) = Schema::of::<Validated>() else {
+            panic!("not a struct")
+        };
+        assert_eq!(fields[0], ("id".to_string(), Schema::U32));
+        assert!(matches!(&fields[1].1, Schema::Enum(variants) if variants.len() == 2));
+        assert!(!format!("{:?}", fields[1].1).contains("Any"));
+        assert_eq!(
+            fields[2],
+            (
+                "inner".to_string(),
+                Schema::Struct(named(&[("a", Schema::U32)]))
+            )
+        );
+    }
+
+    #[test]
+    fn fingerprint() {
+        // The fingerprint is part of the wire protocol, it must never change
+        assert_eq!(Schema::Unit.fingerprint(), 0xaf63_cd4c_8601_d30f);
+        assert_eq!(
+            Schema::Struct(named(&[("a", Schema::U32)])).fingerprint(),
+            Schema::of::<Struct>().fingerprint()
+        );
+        assert_ne!(
+            Schema::Struct(named(&[("a", Schema::U32)])).fingerprint(),
+            Schema::Struct(named(&[("b", Schema::U32)])).fingerprint()
+        );
+    }
+
+    #[test]
+    fn difference() {
+        let user = Schema::of::<User>();
+        assert_eq!(user.difference(&user), None);
+        let Schema::Struct(mut fields) = user.clone() else {
+            unreachable!()
+        };
+        fields.push(("email".to_string(), Schema::Str));
+        let added = Schema::Struct(fields.clone());
+        assert_eq!(user.difference(&added).as_deref(), Some("adds `email`"));
+        assert_eq!(added.difference(&user).as_deref(), Some("removes `email`"));
+        fields.swap(0, 1);
+        assert_eq!(
+            added.difference(&Schema::Struct(fields)).as_deref(),
+            Some("reorders the fields of the schema")
+        );
+        assert_eq!(
+            Schema::of::<Vec<(u8, u16)>>()
+                .difference(&Schema::of::<Vec<(u8, i16)>>())
+                .as_deref(),
+            Some("has i16 instead of u16 in `[].1`")
+        );
+        assert_eq!(
+            Schema::of::<Expr>()
+                .difference(&Schema::Enum(named(&[(
+                    "Lit",
+                    Schema::Newtype(Box::new(Schema::I64))
+                )])))
+                .as_deref(),
+            Some("removes `Add`")
+        );
+    }
+
+    #[derive(Deserialize)]
+    #[allow(dead_code)]
+    struct Struct {
+        a: u32,
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_core::Stream;
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::{
+    client::CancelOnDrop, response_stream::StreamFrame, BidiStreamable, Client, ClientStreamable,
+    RequestError, ResponseStream, SendError,
+};
+
+/// The inputs a handler receives from the caller of a session
+pub struct Inputs<T> {
+    receiver: UnboundedReceiver<Vec<u8>>,
+    phantom: PhantomData<fn() -> T>,
+}
+
+impl<T> Inputs<T>
+where
+    T: DeserializeOwned,
+{
+    pub(crate) fn new(receiver: UnboundedReceiver<Vec<u8>>) -> Self {
+        Self {
+            receiver,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Wait for the next input, `None` once the caller has closed its inputs
+    /// or sent one that could not be decoded
+    pub async fn recv(&mut self) -> Option<T> {
+        crate::decode(&self.receiver.recv().await?).ok()
+    }
+}
+
+impl<T> Stream for Inputs<T>
+where
+    T: DeserializeOwned,
+{
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut()
+            .receiver
+            .poll_recv(cx)
+            .map(|data| crate::decode(&data?).ok())
+    }
+}
+
+/// A session sending inputs to a [`ClientStreamable`] handler, created by
+/// [`Client::request_client_stream`]
+///
+/// Dropping the session before it finishes cancels it
+pub struct ClientStream<'a, R> {
+    client: &'a Client,
+    id: u32,
+    receiver: UnboundedReceiver<StreamFrame>,
+    path: &'static str,
+    _cancel: CancelOnDrop,
+    phantom: PhantomData<fn() -> R>,
+}
+
+impl<'a, R> ClientStream<'a, R>
+where
+    R: ClientStreamable,
+{
+    pub(crate) fn new(
+        client: &'a Client,
+        id: u32,
+        receiver: UnboundedReceiver<StreamFrame>,
+        path: &'static str,
+        cancel: CancelOnDrop,
+    ) -> Self {
+        Self {
+            client,
+            id,
+            receiver,
+            path,
+            _cancel: cancel,
+            phantom: PhantomData,
+        }
+    }
+
+    pub async fn send(&self, input: &R::Input) -> Result<(), SendError> {
+        self.client.send_input(self.id, input).await
+    }
+
+    /// Close the inputs and wait for the handler's response
+    pub async fn finish(mut self) -> Result<R::Response, RequestError<R::Error>> {
+        self.client.close_input(self.id).await?;
+        loop {
+            match self.receiver.recv().await {
+                Some(StreamFrame::Item(_)) => {}
+                Some(StreamFrame::End(response)) => {
+                    let data = crate::response_data(response, self.path, R::schema)?;
+                    return crate::decode(&data).map_err(RequestError::Decode);
+                }
+                None => return Err(RequestError::ConnectionLost),
+            }
+        }
+    }
+}
+
+/// A session exchanging streams with a [`BidiStreamable`] handler, created by
+/// [`Client::request_bidi_stream`]
+///
+/// Dropping the session before it ends cancels it
+pub struct BidiStream<'a, R> {
+    client: &'a Client,
+    id: u32,
+    responses: ResponseStream<R>,
+}
+
+impl<'a, R> BidiStream<'a, R>
+where
+    R: BidiStreamable,
+{
+    pub(crate) fn new(
+        client: &'a Client,
+        id: u32,
+        receiver: UnboundedReceiver<StreamFrame>,
+        path: &'static str,
+        cancel: CancelOnDrop,
+    ) -> Self {
+        Self {
+            client,
+            id,
+            responses: ResponseStream::new(receiver, path, R::schema, cancel),
+        }
+    }
+
+    pub async fn send(&self, input: &R::Input) -> Result<(), SendError> {
+        self.client.send_input(self.id, input).await
+    }
+
+    /// Tell the handler no more inputs will be sent, responses can still be received
+    pub async fn close(&self) -> Result<(), SendError> {
+        self.client.close_input(self.id).await
+    }
+
+    /// Wait for the next response, `None` once the handler has finished
+    ///
+    /// An error ends the session
+    pub async fn next(&mut self) -> Option<Result<R::Response, RequestError<R::Error>>> {
+        self.responses.next().await
+    }
+}
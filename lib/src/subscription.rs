@@ -0,0 +1,78 @@
+use std::{
+    marker::PhantomData,
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::SystemTime,
+};
+
+use futures_core::Stream;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedReceiver;
+
+use crate::{internals::EventPublish, Eventable};
+
+/// Where a subscription starts in the broker's event log, see [`crate::Client::subscribe_from`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Position {
+    /// The event at this offset, or the oldest one still kept if it was removed
+    Offset(u64),
+    /// The first event logged at or after this time
+    Time(SystemTime),
+}
+
+/// A stream of events of type `E`, created by [`crate::Client::subscribe`]
+pub struct Subscription<E> {
+    receiver: UnboundedReceiver<EventPublish>,
+    phantom: PhantomData<fn() -> E>,
+}
+
+impl<E> Subscription<E>
+where
+    E: Eventable,
+{
+    pub(crate) fn new(receiver: UnboundedReceiver<EventPublish>) -> Self {
+        Self {
+            receiver,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Wait for the next event, `None` once the client has shut down
+    ///
+    /// Events that can not be decoded as `E`, such as ones published with another
+    /// version of it, are skipped
+    pub async fn recv(&mut self) -> Option<E> {
+        self.recv_with_offset().await.map(|(event, _)| event)
+    }
+
+    /// Wait for the next event and its offset in the broker's event log,
+    /// the offset is `None` when the broker does not log events
+    pub async fn recv_with_offset(&mut self) -> Option<(E, Option<u64>)> {
+        std::future::poll_fn(|cx| self.poll_event(cx)).await
+    }
+
+    fn poll_event(&mut self, cx: &mut Context<'_>) -> Poll<Option<(E, Option<u64>)>> {
+        loop {
+            let Some(event) = ready!(self.receiver.poll_recv(cx)) else {
+                return Poll::Ready(None);
+            };
+            let offset = event.offset;
+            if let Ok(event) = E::from_event(event) {
+                return Poll::Ready(Some((event, offset)));
+            }
+        }
+    }
+}
+
+impl<E> Stream for Subscription<E>
+where
+    E: Eventable,
+{
+    type Item = E;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut()
+            .poll_event(cx)
+            .map(|event| event.map(|(event, _)| event))
+    }
+}
@@ -0,0 +1,157 @@
+use std::{fmt::Debug, io, sync::Arc};
+
+use rustls::{
+    crypto::ring::default_provider,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+    ClientConfig, RootCertStore, ServerConfig,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::{client, server, TlsAcceptor, TlsConnector};
+
+use crate::{
+    internals::BoxStream,
+    transport::{Listener, Transport},
+};
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e)
+}
+
+/// TLS for clients and responders, trusting only the given certificate authority
+#[derive(Clone)]
+pub struct TlsClientConfig {
+    connector: TlsConnector,
+    server_name: ServerName<'static>,
+}
+
+impl TlsClientConfig {
+    /// `ca` holds the PEM encoded certificates the broker's certificate must chain to,
+    /// `server_name` is the name the broker's certificate must be valid for
+    pub fn from_pem(ca: &[u8], server_name: &str) -> io::Result<Self> {
+        let mut roots = RootCertStore::empty();
+        for cert in CertificateDer::pem_slice_iter(ca) {
+            roots.add(cert.map_err(invalid)?).map_err(invalid)?;
+        }
+        let config = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(invalid)?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Ok(Self {
+            connector: TlsConnector::from(Arc::new(config)),
+            server_name: ServerName::try_from(server_name.to_string()).map_err(invalid)?,
+        })
+    }
+
+    pub async fn connect<S>(&self, stream: S) -> io::Result<client::TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.connector
+            .connect(self.server_name.clone(), stream)
+            .await
+    }
+}
+
+impl Debug for TlsClientConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsClientConfig")
+            .field("server_name", &self.server_name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// TLS for the broker
+#[derive(Clone)]
+pub struct TlsServerConfig {
+    acceptor: TlsAcceptor,
+}
+
+impl TlsServerConfig {
+    /// `cert_chain` holds the PEM encoded certificate chain of the broker,
+    /// `key` the PEM encoded private key of its certificate
+    pub fn from_pem(cert_chain: &[u8], key: &[u8]) -> io::Result<Self> {
+        let certs = CertificateDer::pem_slice_iter(cert_chain)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(invalid)?;
+        let key = PrivateKeyDer::from_pem_slice(key).map_err(invalid)?;
+        let config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(invalid)?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(invalid)?;
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+        })
+    }
+
+    pub async fn accept<S>(&self, stream: S) -> io::Result<server::TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.acceptor.accept(stream).await
+    }
+}
+
+impl Debug for TlsServerConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsServerConfig").finish_non_exhaustive()
+    }
+}
+
+/// Encrypt connections made by another transport
+pub struct TlsTransport<T> {
+    inner: T,
+    config: TlsClientConfig,
+}
+
+impl<T> TlsTransport<T>
+where
+    T: Transport,
+{
+    pub fn new(inner: T, config: TlsClientConfig) -> Self {
+        Self { inner, config }
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> Transport for TlsTransport<T>
+where
+    T: Transport,
+{
+    async fn connect(&self) -> io::Result<BoxStream> {
+        let stream = self.inner.connect().await?;
+        Ok(Box::new(self.config.connect(stream).await?))
+    }
+}
+
+/// Encrypt connections accepted by another listener
+pub struct TlsListener<L> {
+    inner: L,
+    config: TlsServerConfig,
+}
+
+impl<L> TlsListener<L>
+where
+    L: Listener,
+{
+    pub fn new(inner: L, config: TlsServerConfig) -> Self {
+        Self { inner, config }
+    }
+}
+
+#[async_trait::async_trait]
+impl<L> Listener for TlsListener<L>
+where
+    L: Listener,
+{
+    async fn accept(&self) -> io::Result<BoxStream> {
+        self.inner.accept().await
+    }
+
+    async fn handshake(&self, stream: BoxStream) -> io::Result<BoxStream> {
+        let stream = self.inner.handshake(stream).await?;
+        Ok(Box::new(self.config.accept(stream).await?))
+    }
+}
@@ -0,0 +1,283 @@
+use std::io::ErrorKind;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter, ReadHalf, WriteHalf};
+
+use crate::{
+    internals::{BoxStream, Message},
+    FrameTooLarge,
+};
+
+/// The largest frame a connection sends or receives unless configured otherwise
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// How much of a frame over the maximum size is read to find what it was part of
+const HEAD_SIZE: usize = 64;
+
+/// A frame over the maximum size that was read past, so the connection can still be used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SkippedFrame {
+    pub error: FrameTooLarge,
+    pub part: FramePart,
+}
+
+/// What a skipped frame was part of, found from its start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramePart {
+    /// A request or an input of a session with this id, sent by its caller
+    Request(u32),
+    /// A response or an item of a streamed response with this id, sent by its handler
+    Response(u32),
+    /// Anything else, such as an event
+    Other,
+}
+
+impl SkippedFrame {
+    /// Find a `SkippedFrame` wrapped in an IO error by a connection
+    pub fn from_io(e: &std::io::Error) -> Option<Self> {
+        e.get_ref()?.downcast_ref().copied()
+    }
+}
+
+impl std::fmt::Display for SkippedFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "skipped a {}", self.error)
+    }
+}
+
+impl std::error::Error for SkippedFrame {}
+
+impl FramePart {
+    /// Decode the start of a message, which is an array holding a map from the name of
+    /// the payload's variant to the payload, whose first field is the id of a request
+    fn of(mut head: &[u8]) -> Self {
+        let mut part = || {
+            if rmp::decode::read_array_len(&mut head).ok()? != 1
+                || rmp::decode::read_map_len(&mut head).ok()? != 1
+            {
+                return None;
+            }
+            let mut name = [0; 32];
+            let part: fn(u32) -> Self = match rmp::decode::read_str(&mut head, &mut name).ok()? {
+                "RequestAsk" | "SessionSend" => Self::Request,
+                "RequestResponse" | "StreamItem" | "StreamEnd" => Self::Response,
+                _ => return None,
+            };
+            rmp::decode::read_array_len(&mut head).ok()?;
+            Some(part(rmp::decode::read_int(&mut head).ok()?))
+        };
+        part().unwrap_or(Self::Other)
+    }
+}
+
+/// Length prefixed message frames over a stream from any transport
+pub struct Connection {
+    stream: BufReader<BoxStream>,
+    max_frame_size: usize,
+}
+
+impl Connection {
+    pub fn new(stream: BoxStream) -> Self {
+        Self::with_max_frame_size(stream, DEFAULT_MAX_FRAME_SIZE)
+    }
+
+    /// Frames larger than `max_frame_size` bytes are rejected in both directions
+    pub fn with_max_frame_size(stream: BoxStream, max_frame_size: usize) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+            max_frame_size,
+        }
+    }
+
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size;
+    }
+
+    /// Read the next message, `None` once the peer has closed the connection
+    pub async fn read(&mut self) -> std::io::Result<Option<Message>> {
+        read_message(&mut self.stream, self.max_frame_size).await
+    }
+
+    pub async fn write(&mut self, message: &Message) -> std::io::Result<()> {
+        write_message(&mut self.stream, message, self.max_frame_size).await
+    }
+
+    /// Split the connection so reading and writing can happen concurrently
+    pub fn into_split(self) -> (ConnectionReader, ConnectionWriter) {
+        let (read, write) = tokio::io::split(self.stream);
+        (
+            ConnectionReader {
+                read,
+                max_frame_size: self.max_frame_size,
+            },
+            ConnectionWriter {
+                write: BufWriter::new(write),
+                max_frame_size: self.max_frame_size,
+            },
+        )
+    }
+}
+
+pub struct ConnectionReader {
+    read: ReadHalf<BufReader<BoxStream>>,
+    max_frame_size: usize,
+}
+
+impl ConnectionReader {
+    /// Read the next message, `None` once the peer has closed the connection
+    ///
+    /// A frame over the maximum size is skipped and returned as a [`SkippedFrame`] error,
+    /// the connection can still be read afterwards
+    pub async fn read(&mut self) -> std::io::Result<Option<Message>> {
+        read_message(&mut self.read, self.max_frame_size).await
+    }
+}
+
+pub struct ConnectionWriter {
+    write: BufWriter<WriteHalf<BufReader<BoxStream>>>,
+    max_frame_size: usize,
+}
+
+impl ConnectionWriter {
+    /// Write a message
+    ///
+    /// A message over the maximum frame size is an error, but nothing is written
+    /// so the connection can still be used
+    pub async fn write(&mut self, message: &Message) -> std::io::Result<()> {
+        write_message(&mut self.write, message, self.max_frame_size).await
+    }
+}
+
+async fn read_message<R>(read: &mut R, max: usize) -> std::io::Result<Option<Message>>
+where
+    R: AsyncReadExt + Unpin,
+{
+    let size = match read.read_u32().await {
+        Ok(size) => size as usize,
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if size == 0 {
+        return Ok(None);
+    }
+    if size > max {
+        let mut head = vec![0; size.min(HEAD_SIZE)];
+        read.read_exact(&mut head).await?;
+        let rest = (size - head.len()) as u64;
+        if tokio::io::copy(&mut (&mut *read).take(rest), &mut tokio::io::sink()).await? < rest {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            SkippedFrame {
+                error: FrameTooLarge { size, max },
+                part: FramePart::of(&head),
+            },
+        ));
+    }
+    let mut buf = vec![0; size];
+    read.read_exact(&mut buf).await?;
+    Message::from_bytes(&buf)
+        .map(Some)
+        .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
+}
+
+async fn write_message<W>(write: &mut W, message: &Message, max: usize) -> std::io::Result<()>
+where
+    W: AsyncWriteExt + Unpin,
+{
+    let bytes = message.to_bytes();
+    // The length prefix can not describe anything larger than a u32
+    if bytes.len() > max.min(u32::MAX as usize) {
+        return Err(FrameTooLarge {
+            size: bytes.len(),
+            max,
+        }
+        .into());
+    }
+    write.write_u32(bytes.len() as u32).await?;
+    write.write_all(&bytes).await?;
+    write.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Connection, FramePart, SkippedFrame};
+    use crate::{
+        internals::{Control, EventPublish, Message, Payload, RequestResponse, ResponseStatus},
+        FrameTooLarge,
+    };
+
+    #[tokio::test]
+    async fn round_trip() {
+        let (a, b) = tokio::io::duplex(64);
+        let mut a = Connection::new(Box::new(a));
+        let (mut read, mut write) = Connection::new(Box::new(b)).into_split();
+
+        a.write(&Message::new(Payload::Control(Control::Ping)))
+            .await
+            .unwrap();
+        assert!(matches!(
+            read.read().await.unwrap().unwrap().payload,
+            Payload::Control(Control::Ping)
+        ));
+        write
+            .write(&Message::new(Payload::Control(Control::Pong)))
+            .await
+            .unwrap();
+        assert!(matches!(
+            a.read().await.unwrap().unwrap().payload,
+            Payload::Control(Control::Pong)
+        ));
+
+        drop(a);
+        assert!(read.read().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn max_frame_size() {
+        let event = |size| {
+            Message::new(Payload::EventPublish(EventPublish {
+                path: String::new(),
+                data: vec![0; size],
+                offset: None,
+            }))
+        };
+        let (a, b) = tokio::io::duplex(64 * 1024);
+        let mut a = Connection::new(Box::new(a));
+        let mut b = Connection::with_max_frame_size(Box::new(b), 1024);
+
+        // An oversized message is refused without writing anything
+        let e = b.write(&event(2048)).await.unwrap_err();
+        assert!(matches!(
+            FrameTooLarge::from_io(&e),
+            Some(FrameTooLarge { max: 1024, .. })
+        ));
+        b.write(&event(512)).await.unwrap();
+        assert!(a.read().await.unwrap().is_some());
+
+        // An oversized frame from the peer is skipped, telling what it was part of
+        a.write(&event(2048)).await.unwrap();
+        let e = b.read().await.unwrap_err();
+        assert!(matches!(
+            SkippedFrame::from_io(&e),
+            Some(SkippedFrame {
+                error: FrameTooLarge { max: 1024, .. },
+                part: FramePart::Other,
+            })
+        ));
+        a.write(&Message::new(Payload::RequestResponse(RequestResponse {
+            id: 7,
+            status: ResponseStatus::Ok,
+            data: vec![0; 2048],
+        })))
+        .await
+        .unwrap();
+        let e = b.read().await.unwrap_err();
+        assert_eq!(
+            SkippedFrame::from_io(&e).map(|skipped| skipped.part),
+            Some(FramePart::Response(7))
+        );
+        a.write(&event(512)).await.unwrap();
+        assert!(b.read().await.unwrap().is_some());
+    }
+}
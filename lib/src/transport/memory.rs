@@ -0,0 +1,60 @@
+use std::io::ErrorKind;
+
+use tokio::{
+    io::DuplexStream,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+        Mutex,
+    },
+};
+
+use super::{Listener, Transport};
+use crate::internals::BoxStream;
+
+/// How many bytes each direction of an in-memory connection buffers
+const BUFFER: usize = 64 * 1024;
+
+/// Create a transport connected to a listener in the same process, without a socket
+pub fn memory() -> (MemoryTransport, MemoryListener) {
+    let (accept, incoming) = unbounded_channel();
+    (
+        MemoryTransport { accept },
+        MemoryListener {
+            incoming: Mutex::new(incoming),
+        },
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryTransport {
+    accept: UnboundedSender<DuplexStream>,
+}
+
+#[async_trait::async_trait]
+impl Transport for MemoryTransport {
+    async fn connect(&self) -> std::io::Result<BoxStream> {
+        let (client, server) = tokio::io::duplex(BUFFER);
+        self.accept.send(server).map_err(|_| {
+            std::io::Error::new(ErrorKind::ConnectionRefused, "the listener was dropped")
+        })?;
+        Ok(Box::new(client))
+    }
+}
+
+#[derive(Debug)]
+pub struct MemoryListener {
+    incoming: Mutex<UnboundedReceiver<DuplexStream>>,
+}
+
+#[async_trait::async_trait]
+impl Listener for MemoryListener {
+    async fn accept(&self) -> std::io::Result<BoxStream> {
+        match self.incoming.lock().await.recv().await {
+            Some(stream) => Ok(Box::new(stream)),
+            None => Err(std::io::Error::new(
+                ErrorKind::NotConnected,
+                "every transport was dropped",
+            )),
+        }
+    }
+}
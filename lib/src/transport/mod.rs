@@ -0,0 +1,130 @@
+use tokio::net::TcpListener;
+
+use crate::{internals::BoxStream, Address};
+
+mod connection;
+pub use connection::{
+    Connection, ConnectionReader, ConnectionWriter, FramePart, SkippedFrame, DEFAULT_MAX_FRAME_SIZE,
+};
+
+mod memory;
+pub use memory::{memory, MemoryListener, MemoryTransport};
+
+/// A way for clients and responders to reach the broker
+#[async_trait::async_trait]
+pub trait Transport: Send + Sync {
+    async fn connect(&self) -> std::io::Result<BoxStream>;
+}
+
+/// A way for the broker to accept connections
+#[async_trait::async_trait]
+pub trait Listener: Send + Sync {
+    async fn accept(&self) -> std::io::Result<BoxStream>;
+
+    /// Prepare an accepted stream for use, called on the connection's own task
+    /// so a slow peer does not hold up `accept`
+    async fn handshake(&self, stream: BoxStream) -> std::io::Result<BoxStream> {
+        Ok(stream)
+    }
+}
+
+#[async_trait::async_trait]
+impl<T> Transport for Box<T>
+where
+    T: Transport + ?Sized,
+{
+    async fn connect(&self) -> std::io::Result<BoxStream> {
+        (**self).connect().await
+    }
+}
+
+#[async_trait::async_trait]
+impl<L> Listener for Box<L>
+where
+    L: Listener + ?Sized,
+{
+    async fn accept(&self) -> std::io::Result<BoxStream> {
+        (**self).accept().await
+    }
+
+    async fn handshake(&self, stream: BoxStream) -> std::io::Result<BoxStream> {
+        (**self).handshake(stream).await
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for Address {
+    async fn connect(&self) -> std::io::Result<BoxStream> {
+        match self {
+            Self::Tcp(address) => Ok(Box::new(tokio::net::TcpStream::connect(address).await?)),
+            #[cfg(unix)]
+            Self::Unix(path) => Ok(Box::new(tokio::net::UnixStream::connect(path).await?)),
+            #[cfg(not(unix))]
+            Self::Unix(_) => Err(unsupported()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Listener for TcpListener {
+    async fn accept(&self) -> std::io::Result<BoxStream> {
+        Ok(Box::new(Self::accept(self).await?.0))
+    }
+}
+
+#[cfg(unix)]
+#[async_trait::async_trait]
+impl Listener for tokio::net::UnixListener {
+    async fn accept(&self) -> std::io::Result<BoxStream> {
+        Ok(Box::new(Self::accept(self).await?.0))
+    }
+}
+
+/// Listen for connections on `address`
+pub async fn bind(address: &Address) -> std::io::Result<Box<dyn Listener>> {
+    match address {
+        Address::Tcp(address) => Ok(Box::new(TcpListener::bind(address).await?)),
+        #[cfg(unix)]
+        Address::Unix(path) => {
+            remove_stale_socket(path).await?;
+            Ok(Box::new(tokio::net::UnixListener::bind(path)?))
+        }
+        #[cfg(not(unix))]
+        Address::Unix(_) => Err(unsupported()),
+    }
+}
+
+/// Remove a socket file left behind by a previous broker, which would make the bind fail,
+/// a socket something still listens on or a file of another type is an error instead
+#[cfg(unix)]
+async fn remove_stale_socket(path: &std::path::Path) -> std::io::Result<()> {
+    use std::{io, os::unix::fs::FileTypeExt};
+
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+    match tokio::net::UnixStream::connect(path).await {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("something is already listening on {}", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => std::fs::remove_file(path),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(not(unix))]
+fn unsupported() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "unix domain sockets are not supported on this platform",
+    )
+}
@@ -25,3 +25,102 @@ async fn test_parse() {
     println!("res: {:?}", res);
     println!("path: {:?}", Add::path());
 }
+
+#[tokio::test]
+async fn test_handler_error() {
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum DivError {
+        DivideByZero,
+    }
+    mees::requests! {
+        Div (i32, i32) -> i32 | DivError
+    };
+
+    let mut responder = mees::Responder::new();
+    responder.register(Div::handler(|div| async move {
+        if div.1 == 0 {
+            Err(DivError::DivideByZero)
+        } else {
+            Ok(div.0 / div.1)
+        }
+    }));
+
+    assert_eq!(Div(6, 3).handle_local(&responder).await.unwrap(), 2);
+    match Div(1, 0).handle_local(&responder).await {
+        Err(mees::RequestError::Handler(e)) => assert_eq!(e, DivError::DivideByZero),
+        res => panic!("unexpected result: {res:?}"),
+    }
+}
+
+#[tokio::test]
+async fn test_no_handler() {
+    mees::requests! {
+        Add (i32, i32) -> i32
+    };
+
+    let responder = mees::Responder::new();
+    match Add(1, 2).handle_local(&responder).await {
+        Err(mees::RequestError::NoHandler { path }) => assert_eq!(path, Add::path()),
+        res => panic!("unexpected result: {res:?}"),
+    }
+}
+
+#[test]
+fn test_path_fingerprint() {
+    mod v1 {
+        use mees::Requestable;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Serialize, Deserialize)]
+        pub struct User {
+            pub name: String,
+        }
+        mees::requests! {
+            GetUser (u32) -> User
+        }
+
+        pub fn path() -> &'static str {
+            GetUser::path()
+        }
+    }
+    mod v1_reformatted {
+        use mees::Requestable;
+        use serde::{Deserialize, Serialize};
+
+        /// Renamed, with the same shape
+        #[derive(Serialize, Deserialize)]
+        pub struct Profile {
+            pub name: String,
+        }
+        mees::requests! {
+            /// Looks up a user
+            GetUser(u32)->Profile
+        }
+
+        pub fn path() -> &'static str {
+            GetUser::path()
+        }
+    }
+    mod v2 {
+        use mees::Requestable;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Serialize, Deserialize)]
+        pub struct User {
+            pub name: String,
+            pub email: String,
+        }
+        mees::requests! {
+            GetUser (u32) -> User
+        }
+
+        pub fn path() -> &'static str {
+            GetUser::path()
+        }
+    }
+
+    assert_eq!(v1::path(), v1_reformatted::path());
+    // A field added to the response type changes the path
+    assert_ne!(v1::path(), v2::path());
+    assert!(v1::path().starts_with("GetUser-"));
+}
@@ -0,0 +1,93 @@
+use proc_macro::TokenStream;
+use proc_macro2::Ident;
+use syn::{
+    parse::{Parse, ParseStream},
+    Attribute, Result,
+};
+
+use crate::requests::Data;
+
+pub fn events(item: TokenStream) -> TokenStream {
+    let defs = syn::parse_macro_input!(item as Definitions);
+    let arms = defs.arms;
+
+    let mut events = Vec::new();
+
+    for def in &arms {
+        events.push({
+            let attrs = &def.attrs;
+            let ident = &def.ident;
+            let data = &def.data;
+            let name = ident.to_string();
+            quote::quote!(
+                #[derive(Debug, serde::Serialize, serde::Deserialize)]
+                #(#attrs)*
+                struct #ident #data
+                #[mees::async_trait::async_trait]
+                impl mees::Eventable for #ident {
+                    fn path() -> &'static str {
+                        // Fingerprinted from the traced schema of the event, once
+                        static PATH: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+                        PATH.get_or_init(|| {
+                            mees::internals::path(#name, &<Self as mees::Eventable>::schema())
+                        })
+                    }
+                }
+            )
+        });
+    }
+    TokenStream::from(quote::quote!(
+        #(#events)*
+    ))
+}
+
+struct Definitions {
+    arms: Vec<Definition>,
+}
+
+impl Parse for Definitions {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut arms = Vec::new();
+        while !input.is_empty() {
+            arms.push(input.parse::<Definition>()?);
+        }
+        Ok(Self { arms })
+    }
+}
+
+struct Definition {
+    attrs: Vec<Attribute>,
+    ident: Ident,
+    data: Box<Data>,
+}
+
+impl Parse for Definition {
+    fn parse(input: ParseStream) -> Result<Self> {
+        Ok(Self {
+            attrs: input.call(Attribute::parse_outer)?,
+            ident: input.parse::<Ident>()?,
+            data: Box::new(input.parse::<Data>()?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use quote::ToTokens;
+
+    use super::*;
+
+    #[test]
+    fn parse_definitions() {
+        let input = quote::quote!(
+            /// A user was created
+            UserCreated { id: u32 }
+            Tick (u64)
+        );
+        let output = syn::parse2::<Definitions>(input).unwrap();
+        assert_eq!(output.arms.len(), 2);
+        assert_eq!(output.arms[0].attrs.len(), 1);
+        assert_eq!(output.arms[0].ident.to_string(), "UserCreated");
+        assert_eq!(output.arms[1].data.to_token_stream().to_string(), "(u64) ;");
+    }
+}
@@ -3,9 +3,15 @@
 
 use proc_macro::TokenStream;
 
+mod events;
 mod requests;
 
 #[proc_macro]
 pub fn requests(item: TokenStream) -> TokenStream {
     requests::requests(item)
 }
+
+#[proc_macro]
+pub fn events(item: TokenStream) -> TokenStream {
+    events::events(item)
+}
@@ -1,8 +1,3 @@
-use std::{
-    collections::hash_map::DefaultHasher,
-    hash::{Hash, Hasher},
-};
-
 use proc_macro::TokenStream;
 use proc_macro2::Ident;
 use quote::ToTokens;
@@ -11,9 +6,13 @@ use syn::{
     parse::{Parse, ParseStream},
     punctuated::Punctuated,
     token::{Paren, RArrow},
-    Attribute, Field, FieldsUnnamed, Result, Token, Type,
+    Attribute, Field, FieldsUnnamed, Member, Result, Token, Type,
 };
 
+mod kw {
+    syn::custom_keyword!(stream);
+}
+
 pub fn requests(item: TokenStream) -> TokenStream {
     let defs = syn::parse_macro_input!(item as Definitions);
     let arms = defs.arms;
@@ -27,26 +26,70 @@ pub fn requests(item: TokenStream) -> TokenStream {
             let req = &def.req;
             let response = &def.resp;
             let name = ident.to_string();
-            let path = {
-                let mut s = DefaultHasher::new();
-                def.req.to_token_stream().to_string().hash(&mut s);
-                let req_hash = s.finish();
-                let mut s = DefaultHasher::new();
-                def.resp.to_token_stream().to_string().hash(&mut s);
-                let resp_hash = s.finish();
-                format!("{name}-{req_hash}-{resp_hash}")
+            let (error, error_reply) = def.error.as_ref().map_or_else(
+                || (quote::quote!(mees::Never), quote::quote!()),
+                |(_, error)| {
+                    (
+                        error.to_token_stream(),
+                        quote::quote!(
+                            impl mees::Reply<#ident> for Result<#response, #error> {
+                                fn into_result(self) -> Self {
+                                    self
+                                }
+                            }
+                        ),
+                    )
+                },
+            );
+            let (kind, input) = match (&def.input, def.stream.is_some()) {
+                (Some((_, _, input)), true) => (
+                    quote::quote!(mees::BidiStreamable),
+                    quote::quote!(type Input = #input;),
+                ),
+                (Some((_, _, input)), false) => (
+                    quote::quote!(mees::ClientStreamable),
+                    quote::quote!(type Input = #input;),
+                ),
+                (None, true) => (quote::quote!(mees::Streamable), quote::quote!()),
+                (None, false) => (quote::quote!(mees::Requestable), quote::quote!()),
             };
+            let route_key = def.route_key.as_ref().map(|member| {
+                quote::quote!(
+                    fn route_key(&self) -> Option<Vec<u8>> {
+                        Some(mees::internals::route_key(&self.#member))
+                    }
+                )
+            });
+            let idempotent = def.idempotent.then(|| {
+                quote::quote!(
+                    const IDEMPOTENT: bool = true;
+                )
+            });
             quote::quote!(
                 #[derive(Debug, serde::Serialize, serde::Deserialize)]
                 #(#attrs)*
                 struct #ident #req
-                #[mees::async_trait::async_trait]
-                impl mees::Requestable for #ident {
+                impl mees::Responds for #ident {
                     type Response = #response;
+                    type Error = #error;
+                    #route_key
+                    #idempotent
+                }
+                #[mees::async_trait::async_trait]
+                impl #kind for #ident {
+                    #input
                     fn path() -> &'static str {
-                        #path
+                        // Fingerprinted from the traced schema of the types, once
+                        static PATH: std::sync::OnceLock<String> = std::sync::OnceLock::new();
+                        PATH.get_or_init(|| mees::internals::path(#name, &<Self as #kind>::schema()))
+                    }
+                }
+                impl mees::Reply<#ident> for #response {
+                    fn into_result(self) -> Result<#response, #error> {
+                        Ok(self)
                     }
                 }
+                #error_reply
             )
         });
     }
@@ -71,24 +114,72 @@ impl Parse for Definitions {
 
 struct Definition {
     attrs: Vec<Attribute>,
+    /// Marked with `#[mees(idempotent)]`
+    idempotent: bool,
     ident: Ident,
     req: Box<Data>,
+    /// The field marked with `#[mees(route_key)]`
+    route_key: Option<Member>,
+    input: Option<(Token![<-], kw::stream, Box<Type>)>,
     _fat_arrow_token: RArrow,
+    stream: Option<kw::stream>,
     resp: Box<Type>,
+    error: Option<(Token![|], Box<Type>)>,
 }
 
 impl Parse for Definition {
     fn parse(input: ParseStream) -> Result<Self> {
+        let mut attrs = input.call(Attribute::parse_outer)?;
+        let idempotent = take_idempotent(&mut attrs)?;
+        let ident = input.parse::<Ident>()?;
+        let mut req = input.parse::<Data>()?;
+        let route_key = req.take_route_key()?;
         Ok(Self {
-            attrs: input.call(Attribute::parse_outer)?,
-            ident: input.parse::<Ident>()?,
-            req: Box::new(input.parse::<Data>()?),
+            attrs,
+            idempotent,
+            ident,
+            req: Box::new(req),
+            route_key,
+            input: if input.peek(Token![<-]) {
+                Some((
+                    input.parse()?,
+                    input.parse()?,
+                    Box::new(input.parse::<Type>()?),
+                ))
+            } else {
+                None
+            },
             _fat_arrow_token: input.parse::<RArrow>()?,
+            // `stream::Item` is a path rather than the keyword
+            stream: if input.peek(kw::stream) && !input.peek2(Token![::]) {
+                Some(input.parse()?)
+            } else {
+                None
+            },
             resp: Box::new(input.parse::<Type>()?),
+            error: if input.peek(Token![|]) {
+                Some((input.parse()?, Box::new(input.parse::<Type>()?)))
+            } else {
+                None
+            },
         })
     }
 }
 
+/// Remove the `#[mees(...)]` attributes of a definition, returning whether
+/// it is marked with `#[mees(idempotent)]`
+fn take_idempotent(attrs: &mut Vec<Attribute>) -> Result<bool> {
+    let mut idempotent = false;
+    while let Some(position) = attrs.iter().position(|a| a.path().is_ident("mees")) {
+        let option = attrs.remove(position).parse_args::<Ident>()?;
+        if option != "idempotent" {
+            return Err(syn::Error::new(option.span(), "expected `idempotent`"));
+        }
+        idempotent = true;
+    }
+    Ok(idempotent)
+}
+
 pub enum Data {
     Named(Punctuated<Field, Token![,]>),
     Unnamed(FieldsUnnamed),
@@ -108,6 +199,40 @@ impl Parse for Data {
     }
 }
 
+impl Data {
+    /// Remove the `#[mees(...)]` attributes, which are not valid on the generated struct,
+    /// returning the field marked with `#[mees(route_key)]`
+    fn take_route_key(&mut self) -> Result<Option<Member>> {
+        let fields: Vec<&mut Field> = match self {
+            Self::Named(fields) => fields.iter_mut().collect(),
+            Self::Unnamed(fields) => fields.unnamed.iter_mut().collect(),
+        };
+        let mut route_key = None;
+        for (index, field) in fields.into_iter().enumerate() {
+            while let Some(position) = field.attrs.iter().position(|a| a.path().is_ident("mees")) {
+                let attr = field.attrs.remove(position);
+                let option = attr.parse_args::<Ident>()?;
+                if option != "route_key" {
+                    return Err(syn::Error::new(option.span(), "expected `route_key`"));
+                }
+                if route_key.is_some() {
+                    return Err(syn::Error::new_spanned(
+                        attr,
+                        "only one field can be the route key",
+                    ));
+                }
+                route_key = Some(
+                    field
+                        .ident
+                        .clone()
+                        .map_or_else(|| Member::from(index), Member::Named),
+                );
+            }
+        }
+        Ok(route_key)
+    }
+}
+
 impl ToTokens for Data {
     fn to_tokens(&self, tokens: &mut proc_macro2::TokenStream) {
         match self {
@@ -140,6 +265,87 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_definition_error() {
+        let input = quote::quote!(Div (i32, i32) -> i32 | DivError);
+        let output = syn::parse2::<Definition>(input).unwrap();
+        assert_eq!(output.resp.to_token_stream().to_string(), "i32");
+        assert_eq!(
+            output.error.unwrap().1.to_token_stream().to_string(),
+            "DivError"
+        );
+    }
+
+    #[test]
+    fn parse_definition_stream() {
+        let input = quote::quote!(ListUsers { page: u32 } -> stream User);
+        let output = syn::parse2::<Definition>(input).unwrap();
+        assert!(output.stream.is_some());
+        assert_eq!(output.resp.to_token_stream().to_string(), "User");
+
+        let input = quote::quote!(Get (u32) -> stream::User);
+        let output = syn::parse2::<Definition>(input).unwrap();
+        assert!(output.stream.is_none());
+        assert_eq!(output.resp.to_token_stream().to_string(), "stream :: User");
+    }
+
+    #[test]
+    fn parse_definition_input_stream() {
+        let input = quote::quote!(Upload { name: String } <- stream Vec<u8> -> u64);
+        let output = syn::parse2::<Definition>(input).unwrap();
+        assert_eq!(
+            output.input.unwrap().2.to_token_stream().to_string(),
+            "Vec < u8 >"
+        );
+        assert!(output.stream.is_none());
+
+        let input = quote::quote!(Chat (String) <- stream String -> stream String | ChatError);
+        let output = syn::parse2::<Definition>(input).unwrap();
+        assert!(output.input.is_some());
+        assert!(output.stream.is_some());
+        assert_eq!(
+            output.error.unwrap().1.to_token_stream().to_string(),
+            "ChatError"
+        );
+    }
+
+    #[test]
+    fn parse_definition_route_key() {
+        let input = quote::quote!(GetUser { #[mees(route_key)] id: u32, fresh: bool } -> String);
+        let output = syn::parse2::<Definition>(input).unwrap();
+        assert_eq!(output.route_key.to_token_stream().to_string(), "id");
+        assert_eq!(
+            output.req.to_token_stream().to_string(),
+            "{ id : u32 , fresh : bool }"
+        );
+
+        let input = quote::quote!(Get (bool, #[mees(route_key)] u32) -> String);
+        let output = syn::parse2::<Definition>(input).unwrap();
+        assert_eq!(output.route_key.to_token_stream().to_string(), "1");
+
+        let input = quote::quote!(Get (#[mees(route_key)] u32, #[mees(route_key)] u32) -> String);
+        assert!(syn::parse2::<Definition>(input).is_err());
+        let input = quote::quote!(Get (#[mees(sticky)] u32) -> String);
+        assert!(syn::parse2::<Definition>(input).is_err());
+    }
+
+    #[test]
+    fn parse_definition_idempotent() {
+        let input = quote::quote!(
+            /// Looks up a user
+            #[mees(idempotent)]
+            GetUser (u32) -> String
+        );
+        let output = syn::parse2::<Definition>(input).unwrap();
+        assert!(output.idempotent);
+        assert_eq!(output.attrs.len(), 1);
+
+        let input = quote::quote!(GetUser (u32) -> String);
+        assert!(!syn::parse2::<Definition>(input).unwrap().idempotent);
+        let input = quote::quote!(#[mees(route_key)] GetUser (u32) -> String);
+        assert!(syn::parse2::<Definition>(input).is_err());
+    }
+
     #[test]
     fn parse_response_type_unnamed() {
         let input = quote::quote!((String, i32));